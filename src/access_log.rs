@@ -0,0 +1,122 @@
+use std::net::SocketAddr;
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::request::HttpRequest;
+
+// Like `10/Oct/2000:13:55:36 -0700`
+const COMMON_LOG_DATE_FORMAT: &str = "%d/%b/%Y:%H:%M:%S %z";
+
+// Quotes and control characters would break the quoted request line
+fn escape(value: &str) -> String {
+    value.escape_default().to_string()
+}
+
+fn request_line(request: &HttpRequest) -> String {
+    let url = request.get_url();
+    let mut target = url.resource();
+    if !url.query().is_empty() {
+        target = format!("{}?{}", target, url.query());
+    }
+    escape(&format!(
+        "{:?} {} HTTP/{}",
+        request.get_method(),
+        target,
+        request.get_version()
+    ))
+}
+
+// NCSA Common Log Format: `host ident authuser [date] "request line" status bytes`.
+// Requests that couldn't be parsed are logged with `-` as the request line
+pub fn access_log_line(
+    client_address: SocketAddr,
+    request: Option<&HttpRequest>,
+    user: Option<&str>,
+    status_code: u16,
+    body_size: u64,
+    time: DateTime<FixedOffset>,
+) -> String {
+    let request_line = request.map_or_else(|| String::from("-"), request_line);
+    let user = user.map_or_else(|| String::from("-"), escape);
+    let body_size = if body_size == 0 {
+        String::from("-")
+    } else {
+        body_size.to_string()
+    };
+
+    format!(
+        "{} - {} [{}] \"{}\" {} {}",
+        client_address.ip(),
+        user,
+        time.format(COMMON_LOG_DATE_FORMAT),
+        request_line,
+        status_code,
+        body_size
+    )
+}
+
+// Cached responses are only available as raw bytes
+pub fn raw_response_status_and_body_size(raw_response: &[u8]) -> Option<(u16, u64)> {
+    let status_code = raw_response
+        .split(|byte| *byte == b' ')
+        .nth(1)
+        .and_then(|status_code| std::str::from_utf8(status_code).ok())
+        .and_then(|status_code| status_code.parse().ok())?;
+    let headers_end = raw_response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")?;
+    Some((status_code, (raw_response.len() - headers_end - 4) as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use chrono::TimeZone;
+
+    use crate::request::parse_http_request;
+
+    fn log_time() -> DateTime<FixedOffset> {
+        FixedOffset::west_opt(7 * 3600)
+            .unwrap()
+            .with_ymd_and_hms(2000, 10, 10, 13, 55, 36)
+            .unwrap()
+    }
+
+    #[test]
+    fn access_log_get_request() {
+        let mut stream =
+            Cursor::new(b"GET /echo/hello?lang=en HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec());
+        let request = parse_http_request(&mut stream).unwrap();
+        let client_address = SocketAddr::from(([192, 168, 0, 7], 51234));
+
+        assert_eq!(
+            access_log_line(
+                client_address,
+                Some(&request),
+                Some("frank"),
+                200,
+                2326,
+                log_time()
+            ),
+            "192.168.0.7 - frank [10/Oct/2000:13:55:36 -0700] \"GET /echo/hello?lang=en HTTP/1.1\" 200 2326"
+        );
+        assert_eq!(
+            access_log_line(client_address, None, None, 400, 0, log_time()),
+            "192.168.0.7 - - [10/Oct/2000:13:55:36 -0700] \"-\" 400 -"
+        );
+    }
+
+    #[test]
+    fn access_log_raw_response() {
+        assert_eq!(
+            raw_response_status_and_body_size(
+                b"HTTP/1.1 206 Partial Content\r\ncontent-length: 5\r\n\r\nhello"
+            ),
+            Some((206, 5))
+        );
+        assert_eq!(raw_response_status_and_body_size(b"garbage"), None);
+    }
+}
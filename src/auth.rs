@@ -1,7 +1,10 @@
-use std::str::FromStr;
+use std::{fmt, fs, path::Path, str::FromStr};
 
-use anyhow::{anyhow, Error};
+use anyhow::{anyhow, Context, Error, Result};
 use base64::prelude::*;
+use log::warn;
+use sha2::{Digest, Sha256};
+use subtle::{Choice, ConstantTimeEq};
 
 #[derive(Debug)]
 pub enum AuthMethod {
@@ -9,9 +12,9 @@ pub enum AuthMethod {
     Bearer,
 }
 
-impl ToString for AuthMethod {
-    fn to_string(&self) -> String {
-        format!("{:?}", self)
+impl fmt::Display for AuthMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
     }
 }
 
@@ -26,20 +29,191 @@ impl FromStr for AuthMethod {
     }
 }
 
-pub struct Authenticator {}
+pub const CREDENTIALS_ENV_VAR: &str = "HTTP_SERVER_CREDENTIALS";
+
+type CredentialsDigest = [u8; 32];
+
+// Without credentials every authentication attempt fails
+#[derive(Debug, Clone, Default)]
+pub struct Authenticator {
+    // Only digests of `username:password` are kept in memory
+    credentials: Vec<CredentialsDigest>,
+}
+
 impl Authenticator {
-    pub fn default_credentials() -> String {
-        BASE64_STANDARD.encode("admin:password")
+    pub fn new<'a>(credentials: impl IntoIterator<Item = &'a str>) -> Self {
+        Self {
+            credentials: credentials
+                .into_iter()
+                .map(|credentials| Authenticator::digest(credentials.as_bytes()))
+                .collect(),
+        }
+    }
+
+    // One `username:password` pair per line, empty lines and lines starting with `#` are skipped
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .context(format!("Failed to read credentials from {:?}", path))?;
+        Authenticator::parse_credentials(content.lines())
+    }
+
+    // Comma separated `username:password` pairs
+    pub fn from_env(var_name: &str) -> Result<Self> {
+        let content =
+            std::env::var(var_name).context(format!("Failed to read {} variable", var_name))?;
+        Authenticator::from_comma_separated(&content)
+    }
+
+    fn from_comma_separated(content: &str) -> Result<Self> {
+        Authenticator::parse_credentials(content.split(','))
     }
 
-    pub fn authenticate(data: &[u8], auth_type: &AuthMethod) -> bool {
-        fn auth_basic(data: &[u8]) -> bool {
-            Authenticator::default_credentials().as_bytes().eq(data)
+    fn parse_credentials<'a>(entries: impl Iterator<Item = &'a str>) -> Result<Self> {
+        let credentials = entries
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty() && !entry.starts_with('#'))
+            .map(|entry| match entry.split_once(':') {
+                Some((username, _)) if !username.is_empty() => Ok(entry),
+                _ => Err(anyhow!("Expected `username:password`, got {:?}", entry)),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if credentials.is_empty() {
+            warn!("No credentials were provided, every authentication attempt will fail");
+        }
+        Ok(Authenticator::new(credentials))
+    }
+
+    // Username of valid Basic credentials
+    pub fn username(&self, data: &[u8], auth_type: &AuthMethod) -> Option<String> {
+        if !self.authenticate(data, auth_type) {
+            return None;
         }
+        let credentials = BASE64_STANDARD.decode(data).ok()?;
+        let (username, _) = std::str::from_utf8(&credentials).ok()?.split_once(':')?;
+        Some(username.to_string())
+    }
+
+    fn digest(credentials: &[u8]) -> CredentialsDigest {
+        Sha256::digest(credentials).into()
+    }
+
+    // A regular `==` returns on the first differing byte, so the response time tells an
+    // attacker how much of a guess was right. Compare every byte of every stored digest instead,
+    // so the time spent doesn't depend on the presented credentials.
+    fn is_known(&self, digest: &CredentialsDigest) -> bool {
+        let matched = self
+            .credentials
+            .iter()
+            .fold(Choice::from(0), |matched, known| {
+                matched | known.ct_eq(digest)
+            });
+        matched.into()
+    }
+
+    pub fn authenticate(&self, data: &[u8], auth_type: &AuthMethod) -> bool {
+        let auth_basic = |data: &[u8]| {
+            let Ok(credentials) = BASE64_STANDARD.decode(data) else {
+                return false;
+            };
+            self.is_known(&Authenticator::digest(&credentials))
+        };
 
         match auth_type {
             AuthMethod::Basic => auth_basic(data),
-            _ => panic!("{:?} is not supported", auth_type),
+            _ => {
+                warn!("{:?} authentication is not supported", auth_type);
+                false
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn basic(credentials: &str) -> Vec<u8> {
+        BASE64_STANDARD.encode(credentials).into_bytes()
+    }
+
+    #[test]
+    fn authenticated_username() {
+        let authenticator = Authenticator::new(["alice:secret"]);
+
+        assert_eq!(
+            authenticator
+                .username(&basic("alice:secret"), &AuthMethod::Basic)
+                .unwrap(),
+            "alice"
+        );
+        assert!(authenticator
+            .username(&basic("alice:wrong"), &AuthMethod::Basic)
+            .is_none());
+    }
+
+    #[test]
+    fn authenticate_configured_credentials() {
+        let authenticator = Authenticator::new(["alice:secret", "bob:hunter2"]);
+
+        assert!(authenticator.authenticate(&basic("alice:secret"), &AuthMethod::Basic));
+        assert!(authenticator.authenticate(&basic("bob:hunter2"), &AuthMethod::Basic));
+        assert!(!authenticator.authenticate(&basic("eve:secret"), &AuthMethod::Basic));
+        assert!(!authenticator.authenticate(&basic("admin:password"), &AuthMethod::Basic));
+    }
+
+    #[test]
+    fn authenticate_constant_time_comparison() {
+        let authenticator = Authenticator::new(["alice:secret"]);
+        let mut digest = Authenticator::digest(b"alice:secret");
+        assert!(authenticator.is_known(&digest));
+
+        // Differs only in the last byte
+        digest[31] ^= 1;
+        assert!(!authenticator.is_known(&digest));
+        assert!(!authenticator.authenticate(&basic("alice:secreT"), &AuthMethod::Basic));
+        assert!(!Authenticator::new([]).is_known(&digest));
+    }
+
+    #[test]
+    fn authenticate_credentials_from_file() {
+        let path = std::env::temp_dir().join(format!(
+            "http_server_credentials_{}.txt",
+            std::process::id()
+        ));
+        fs::write(&path, "# users\nalice:secret\n\nbob:hunter2\n").unwrap();
+        let authenticator = Authenticator::from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(authenticator.authenticate(&basic("alice:secret"), &AuthMethod::Basic));
+        assert!(authenticator.authenticate(&basic("bob:hunter2"), &AuthMethod::Basic));
+        assert!(!authenticator.authenticate(&basic("eve:secret"), &AuthMethod::Basic));
+    }
+
+    #[test]
+    fn authenticate_comma_separated_credentials() {
+        let authenticator =
+            Authenticator::from_comma_separated("alice:secret, bob:hunter2").unwrap();
+
+        assert!(authenticator.authenticate(&basic("alice:secret"), &AuthMethod::Basic));
+        assert!(authenticator.authenticate(&basic("bob:hunter2"), &AuthMethod::Basic));
+        assert!(!authenticator.authenticate(&basic("eve:secret"), &AuthMethod::Basic));
+    }
+
+    #[test]
+    fn authenticate_malformed_credentials() {
+        assert!(Authenticator::parse_credentials(["no-separator"].into_iter()).is_err());
+        assert!(
+            !Authenticator::new(["alice:secret"]).authenticate(b"not base64!", &AuthMethod::Basic)
+        );
+        assert!(Authenticator::from_env("HTTP_SERVER_UNSET_CREDENTIALS").is_err());
+    }
+
+    #[test]
+    fn authenticate_without_credentials() {
+        let authenticator = Authenticator::default();
+        assert!(!authenticator.authenticate(&basic("admin:password"), &AuthMethod::Basic));
+        assert!(!authenticator.authenticate(&basic(":"), &AuthMethod::Basic));
+    }
+}
@@ -1,14 +1,16 @@
 use std::{
     collections::HashMap,
-    fs::{self, File},
+    fmt, fs,
     hash::{DefaultHasher, Hash, Hasher},
-    io::Write,
     path::{Path, PathBuf},
     str::FromStr,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::{Error, Result};
-use log::trace;
+use anyhow::{anyhow, Error, Result};
+use log::{error, trace};
+use serde::{Deserialize, Serialize};
 
 use crate::response::HttpResponse;
 
@@ -24,11 +26,12 @@ impl FromStr for CacheControl {
         let cache_directives = s
             .split(',')
             .map(|directive| {
+                // Directive names are case-insensitive
                 let directive = directive.trim();
                 if let Some((key, value)) = directive.split_once('=') {
-                    (key.to_string(), value.to_string())
+                    (key.trim().to_ascii_lowercase(), value.trim().to_string())
                 } else {
-                    (directive.to_string(), "".to_string())
+                    (directive.to_ascii_lowercase(), "".to_string())
                 }
             })
             .collect();
@@ -40,49 +43,767 @@ impl CacheControl {
     pub fn store_allowed(&self) -> bool {
         !self.cache_directives.contains_key("no-store")
     }
+
+    // `no-cache` in a request asks for a response that wasn't served from a cache
+    pub fn retrieve_allowed(&self) -> bool {
+        !self.no_cache()
+    }
+
+    pub fn no_cache(&self) -> bool {
+        self.cache_directives.contains_key("no-cache")
+    }
+
+    // Only meant for the cache of a single user, not for shared ones
+    pub fn is_private(&self) -> bool {
+        self.cache_directives.contains_key("private")
+    }
+
+    // Stale responses can't be used without checking with the origin first
+    pub fn must_revalidate(&self) -> bool {
+        self.cache_directives.contains_key("must-revalidate")
+    }
+
+    pub fn max_age(&self) -> Option<Duration> {
+        self.cache_directives
+            .get("max-age")
+            .and_then(|max_age| max_age.trim_matches('"').parse().ok())
+            .map(Duration::from_secs)
+    }
+}
+
+pub const PATH_TO_CACHE: &str = ".cache";
+pub const DEFAULT_MAX_CACHE_SIZE: u64 = 64 * 1024 * 1024; // 64 MB
+
+// Of the files written by the cache, nothing else in its directory is touched
+const CACHE_ENTRY_EXTENSION: &str = "cached";
+
+// Bodies are stored as base64, a JSON array of numbers would be several times larger
+mod base64_body {
+    use base64::prelude::*;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(body: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&BASE64_STANDARD.encode(body))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let body = String::deserialize(deserializer)?;
+        BASE64_STANDARD
+            .decode(body)
+            .map_err(serde::de::Error::custom)
+    }
 }
 
-const PATH_TO_CACHE: &str = ".cache";
-pub struct Cache {}
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
 
-// TODO: use serde rather than writing the raw data to cache
-impl Cache {
-    fn get_resource_path(resource: &str) -> PathBuf {
+// A stored response with what's needed to send it again and to tell whether it's still fresh
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub version: String,
+    pub status_code: u16,
+    pub reason: String,
+    pub headers: HashMap<String, String>,
+    #[serde(with = "base64_body")]
+    pub body: Vec<u8>,
+    // Seconds since the Unix epoch
+    pub stored_at: u64,
+    pub max_age: Option<u64>,
+}
+
+impl CachedResponse {
+    pub fn new(http_response: &HttpResponse, max_age: Option<Duration>) -> Self {
+        let mut headers = http_response.content().get_headers().clone();
+        let body = http_response.content().get_body().clone();
+        // The whole body is known, it's sent in one piece when replayed
+        if headers.remove("transfer-encoding").is_some() {
+            headers.insert(String::from("content-length"), body.len().to_string());
+        }
+        Self {
+            version: http_response.version().to_string(),
+            status_code: http_response.status_code().get_code_value(),
+            reason: http_response.status_code().to_string(),
+            headers,
+            body,
+            stored_at: unix_time(),
+            max_age: max_age.map(|max_age| max_age.as_secs()),
+        }
+    }
+
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(unix_time().saturating_sub(self.stored_at))
+    }
+
+    // Entries without max-age don't expire
+    pub fn is_fresh(&self) -> bool {
+        self.max_age
+            .is_none_or(|max_age| self.age() < Duration::from_secs(max_age))
+    }
+
+    pub fn add_header(&mut self, header_name: &str, header_content: impl Into<String>) {
+        self.headers
+            .insert(header_name.to_ascii_lowercase(), header_content.into());
+    }
+
+    // The response as it's sent to the client
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut raw_response = format!(
+            "HTTP/{} {} {}\r\n",
+            self.version, self.status_code, self.reason
+        );
+        for (header_name, header_content) in &self.headers {
+            raw_response.push_str(&format!("{}: {}\r\n", header_name, header_content));
+        }
+        raw_response.push_str("\r\n");
+        let mut raw_response = raw_response.into_bytes();
+        raw_response.extend_from_slice(&self.body);
+        raw_response
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    size: u64,
+    // Value of `CacheIndex::clock` at the last access
+    last_access: u64,
+}
+
+// Keeps track of stored entries, so the least recently used ones can be evicted
+#[derive(Debug)]
+struct CacheIndex<K> {
+    entries: HashMap<K, CacheEntry>,
+    total_size: u64,
+    clock: u64,
+}
+
+impl<K> Default for CacheIndex<K> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            total_size: 0,
+            clock: 0,
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone> CacheIndex<K> {
+    fn touch(&mut self, key: &K, size: u64) {
+        self.clock += 1;
+        let entry = CacheEntry {
+            size,
+            last_access: self.clock,
+        };
+        if let Some(previous) = self.entries.insert(key.clone(), entry) {
+            self.total_size -= previous.size;
+        }
+        self.total_size += size;
+    }
+
+    fn bump(&mut self, key: &K) {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.last_access = self.clock;
+        }
+    }
+
+    fn remove(&mut self, key: &K) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.total_size -= entry.size;
+        }
+    }
+
+    fn least_recently_used(&self) -> Option<K> {
+        self.entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_access)
+            .map(|(key, _)| key.clone())
+    }
+}
+
+fn store_allowed(cache_control: &Option<CacheControl>) -> bool {
+    cache_control
+        .as_ref()
+        .is_none_or(CacheControl::store_allowed)
+}
+
+// The index stays consistent even if a thread panicked while holding the lock
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+// Keys start with the method and the resource, like `GET /index.html`, variants of the response
+// follow after another space. Resources are percent-encoded, they don't contain spaces
+fn key_resource(key: &str) -> &str {
+    key.split(' ').nth(1).unwrap_or(key)
+}
+
+// Storage for responses, shared by all connections
+pub trait ResponseStore: fmt::Debug + Send + Sync {
+    fn add(
+        &self,
+        resource: &str,
+        http_response: &HttpResponse,
+        cache_control: &Option<CacheControl>,
+    ) -> Result<()>;
+
+    // Fails when there is no fresh response for the resource
+    fn retrieve(&self, resource: &str) -> Result<CachedResponse>;
+
+    // Drops the responses stored for every method and variant of the resource, like `/index.html`
+    fn invalidate(&self, resource: &str) -> Result<()>;
+}
+
+#[derive(Debug)]
+pub struct DiskCache {
+    directory: PathBuf,
+    // Total size of stored responses in bytes
+    max_size: u64,
+    index: Mutex<CacheIndex<PathBuf>>,
+}
+
+impl Default for DiskCache {
+    fn default() -> Self {
+        DiskCache::new(PATH_TO_CACHE, DEFAULT_MAX_CACHE_SIZE)
+    }
+}
+
+// Every entry is a JSON file named `<hash of the resource>-<hash of the key>`, with
+// `CACHE_ENTRY_EXTENSION`. Entries of a resource can be found without reading them
+impl DiskCache {
+    // Entries left by a previous run are picked up, the oldest ones are evicted first
+    pub fn new(directory: impl Into<PathBuf>, max_size: u64) -> Self {
+        let directory = directory.into();
+        let mut stored_entries = fs::read_dir(&directory)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let path = entry.path();
+                if !DiskCache::is_entry_path(&path) {
+                    return None;
+                }
+                // Symlinks aren't followed, they weren't written by the cache
+                let metadata = entry.metadata().ok()?;
+                if !metadata.is_file() {
+                    return None;
+                }
+                Some((path, metadata.len(), metadata.modified().ok()?))
+            })
+            .collect::<Vec<_>>();
+        stored_entries.sort_by_key(|(_, _, modified)| *modified);
+
+        let mut index = CacheIndex::default();
+        for (resource_path, size, _) in stored_entries {
+            index.touch(&resource_path, size);
+        }
+
+        let cache = Self {
+            directory,
+            max_size,
+            index: Mutex::new(index),
+        };
+        if let Err(err) = cache.evict() {
+            error!("Failed to evict cache entries: {:?}", err);
+        }
+        cache
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    fn hash(value: &str) -> u64 {
         let mut hasher = DefaultHasher::new();
-        resource.hash(&mut hasher);
-        let resource_name = hasher.finish();
-        Path::new(PATH_TO_CACHE).join(resource_name.to_string())
+        value.hash(&mut hasher);
+        hasher.finish()
     }
 
-    pub fn add(
+    fn get_resource_path(&self, resource: &str) -> PathBuf {
+        self.directory.join(format!(
+            "{}-{}.{}",
+            DiskCache::hash(key_resource(resource)),
+            DiskCache::hash(resource),
+            CACHE_ENTRY_EXTENSION
+        ))
+    }
+
+    fn is_entry_path(path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|extension| extension == CACHE_ENTRY_EXTENSION)
+            && path
+                .file_stem()
+                .and_then(|stem| stem.to_str()?.split_once('-'))
+                .is_some_and(|(resource_hash, key_hash)| {
+                    resource_hash.parse::<u64>().is_ok() && key_hash.parse::<u64>().is_ok()
+                })
+    }
+
+    fn lock_index(&self) -> MutexGuard<'_, CacheIndex<PathBuf>> {
+        lock(&self.index)
+    }
+
+    fn remove_entry(index: &mut CacheIndex<PathBuf>, resource_path: &Path) -> Result<()> {
+        index.remove(&resource_path.to_path_buf());
+        if resource_path.exists() {
+            fs::remove_file(resource_path)?;
+        }
+        Ok(())
+    }
+
+    fn evict(&self) -> Result<()> {
+        let mut index = self.lock_index();
+        while index.total_size > self.max_size {
+            let Some(resource_path) = index.least_recently_used() else {
+                break;
+            };
+            trace!("Evicting {:?} from cache", resource_path);
+            DiskCache::remove_entry(&mut index, &resource_path)?;
+        }
+        Ok(())
+    }
+}
+
+impl ResponseStore for DiskCache {
+    fn add(
+        &self,
         resource: &str,
         http_response: &HttpResponse,
         cache_control: &Option<CacheControl>,
     ) -> Result<()> {
-        let store_allowed = if let Some(cache_control) = cache_control {
-            cache_control.store_allowed()
-        } else {
-            true
-        };
-        
-        if store_allowed {
-            fs::create_dir_all(PATH_TO_CACHE)?;
+        if store_allowed(cache_control) {
+            fs::create_dir_all(&self.directory)?;
 
-            let resource_path = Cache::get_resource_path(resource);
-            let mut file = File::create(resource_path)?;
+            let resource_path = self.get_resource_path(resource);
+            let entry = CachedResponse::new(
+                http_response,
+                cache_control.as_ref().and_then(CacheControl::max_age),
+            );
+            let serialized_entry = serde_json::to_vec(&entry)?;
 
             trace!("Adding response for {:?} to cache", resource);
-            file.write_all(&http_response.as_bytes())?;
+            fs::write(&resource_path, &serialized_entry)?;
+
+            self.lock_index()
+                .touch(&resource_path, serialized_entry.len() as u64);
+            self.evict()?;
         }
 
         Ok(())
     }
 
-    pub fn retrieve(resource: &str) -> Result<Vec<u8>> {
+    fn retrieve(&self, resource: &str) -> Result<CachedResponse> {
         trace!("Reading response for {:?} from cache", resource);
 
-        let resource_path = Cache::get_resource_path(resource);
-        let file_content = fs::read(resource_path)?;
-        Ok(file_content)
+        let resource_path = self.get_resource_path(resource);
+        let file_content = fs::read(&resource_path)?;
+        // Entries that can't be read, e.g. written by an older version, are dropped as well
+        let entry = match serde_json::from_slice::<CachedResponse>(&file_content) {
+            Ok(entry) if entry.is_fresh() => entry,
+            Ok(_) => {
+                DiskCache::remove_entry(&mut self.lock_index(), &resource_path)?;
+                return Err(anyhow!("Cached response for {:?} has expired", resource));
+            }
+            Err(err) => {
+                DiskCache::remove_entry(&mut self.lock_index(), &resource_path)?;
+                return Err(anyhow!(
+                    "Cached response for {:?} is invalid: {:?}",
+                    resource,
+                    err
+                ));
+            }
+        };
+        self.lock_index().bump(&resource_path);
+        Ok(entry)
+    }
+
+    fn invalidate(&self, resource: &str) -> Result<()> {
+        let prefix = format!("{}-", DiskCache::hash(resource));
+        let mut index = self.lock_index();
+        let resource_paths = index
+            .entries
+            .keys()
+            .filter(|resource_path| {
+                resource_path
+                    .file_name()
+                    .and_then(|file_name| file_name.to_str())
+                    .is_some_and(|file_name| file_name.starts_with(&prefix))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        for resource_path in resource_paths {
+            trace!("Invalidating {:?} in cache", resource_path);
+            DiskCache::remove_entry(&mut index, &resource_path)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct MemoryCacheState {
+    entries: HashMap<String, CachedResponse>,
+    index: CacheIndex<String>,
+}
+
+// Keeps responses in the process memory, nothing survives a restart
+#[derive(Debug)]
+pub struct MemoryCache {
+    // Total size of stored responses in bytes
+    max_size: u64,
+    state: Mutex<MemoryCacheState>,
+}
+
+impl Default for MemoryCache {
+    fn default() -> Self {
+        MemoryCache::new(DEFAULT_MAX_CACHE_SIZE)
+    }
+}
+
+impl MemoryCache {
+    pub fn new(max_size: u64) -> Self {
+        Self {
+            max_size,
+            state: Mutex::new(MemoryCacheState::default()),
+        }
+    }
+}
+
+impl ResponseStore for MemoryCache {
+    fn add(
+        &self,
+        resource: &str,
+        http_response: &HttpResponse,
+        cache_control: &Option<CacheControl>,
+    ) -> Result<()> {
+        if store_allowed(cache_control) {
+            trace!("Adding response for {:?} to memory cache", resource);
+            let entry = CachedResponse::new(
+                http_response,
+                cache_control.as_ref().and_then(CacheControl::max_age),
+            );
+            let resource = resource.to_string();
+
+            let mut state = lock(&self.state);
+            state.index.touch(&resource, entry.as_bytes().len() as u64);
+            state.entries.insert(resource, entry);
+
+            while state.index.total_size > self.max_size {
+                let Some(resource) = state.index.least_recently_used() else {
+                    break;
+                };
+                trace!("Evicting {:?} from memory cache", resource);
+                state.index.remove(&resource);
+                state.entries.remove(&resource);
+            }
+        }
+        Ok(())
+    }
+
+    fn retrieve(&self, resource: &str) -> Result<CachedResponse> {
+        trace!("Reading response for {:?} from memory cache", resource);
+
+        let resource = resource.to_string();
+        let mut state = lock(&self.state);
+        let entry = state
+            .entries
+            .get(&resource)
+            .ok_or_else(|| anyhow!("No cached response for {:?}", resource))?;
+
+        if !entry.is_fresh() {
+            state.index.remove(&resource);
+            state.entries.remove(&resource);
+            return Err(anyhow!("Cached response for {:?} has expired", resource));
+        }
+
+        let entry = entry.clone();
+        state.index.bump(&resource);
+        Ok(entry)
+    }
+
+    fn invalidate(&self, resource: &str) -> Result<()> {
+        let mut state = lock(&self.state);
+        let keys = state
+            .entries
+            .keys()
+            .filter(|key| key_resource(key) == resource)
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in keys {
+            trace!("Invalidating {:?} in memory cache", key);
+            state.index.remove(&key);
+            state.entries.remove(&key);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::{ResponseCode, SuccessCode},
+        response::HttpResponseBuilder,
+    };
+
+    fn ok_response() -> HttpResponse {
+        HttpResponseBuilder::default(ResponseCode::Success(SuccessCode::Ok)).build()
+    }
+
+    // Entries take up the size of their file on disk, and of the raw response in memory
+    fn disk_entry_size() -> u64 {
+        let entry = CachedResponse::new(&ok_response(), None);
+        serde_json::to_vec(&entry).unwrap().len() as u64
+    }
+
+    fn memory_entry_size() -> u64 {
+        CachedResponse::new(&ok_response(), None).as_bytes().len() as u64
+    }
+
+    // Every test gets its own directory, so evictions don't interfere
+    fn test_disk_cache(name: &str, max_size: u64) -> DiskCache {
+        let directory = std::env::temp_dir().join(format!("http_server_cache_{}", name));
+        let _ = fs::remove_dir_all(&directory);
+        DiskCache::new(directory, max_size)
+    }
+
+    fn test_stores(name: &str, max_size: u64) -> Vec<Box<dyn ResponseStore>> {
+        vec![
+            Box::new(test_disk_cache(name, max_size)),
+            Box::new(MemoryCache::new(max_size)),
+        ]
+    }
+
+    #[test]
+    fn cache_control_max_age() {
+        let cache_control: CacheControl = "public, max-age=60".parse().unwrap();
+        assert_eq!(cache_control.max_age(), Some(Duration::from_secs(60)));
+        assert!(cache_control.store_allowed());
+
+        let cache_control: CacheControl = "no-store".parse().unwrap();
+        assert_eq!(cache_control.max_age(), None);
+        assert!(!cache_control.store_allowed());
+        assert!(cache_control.retrieve_allowed());
+
+        let cache_control: CacheControl = "no-cache, max-age=5".parse().unwrap();
+        assert!(cache_control.store_allowed());
+        assert!(!cache_control.retrieve_allowed());
+
+        let cache_control: CacheControl = "max-age=soon".parse().unwrap();
+        assert_eq!(cache_control.max_age(), None);
+    }
+
+    #[test]
+    fn cache_control_directives() {
+        let cache_control: CacheControl = "max-age=120, private".parse().unwrap();
+        assert_eq!(cache_control.max_age(), Some(Duration::from_secs(120)));
+        assert!(cache_control.is_private());
+        assert!(!cache_control.no_cache());
+        assert!(!cache_control.must_revalidate());
+        assert!(cache_control.store_allowed());
+
+        let cache_control: CacheControl = "no-cache".parse().unwrap();
+        assert!(cache_control.no_cache());
+        assert!(!cache_control.is_private());
+        assert_eq!(cache_control.max_age(), None);
+
+        let cache_control: CacheControl = "no-store, must-revalidate".parse().unwrap();
+        assert!(!cache_control.store_allowed());
+        assert!(cache_control.must_revalidate());
+        assert!(!cache_control.no_cache());
+        assert!(!cache_control.is_private());
+
+        let cache_control: CacheControl = "Max-Age = 30, No-Cache".parse().unwrap();
+        assert_eq!(cache_control.max_age(), Some(Duration::from_secs(30)));
+        assert!(cache_control.no_cache());
+    }
+
+    #[test]
+    fn cache_round_trip() {
+        let response = ok_response();
+        for store in test_stores("round_trip", DEFAULT_MAX_CACHE_SIZE) {
+            assert!(store.retrieve("/round-trip").is_err(), "{:?}", store);
+            store.add("/round-trip", &response, &None).unwrap();
+            let cached_response = store.retrieve("/round-trip").unwrap();
+            assert_eq!(cached_response.status_code, 200, "{:?}", store);
+            assert_eq!(
+                &cached_response.headers,
+                response.content().get_headers(),
+                "{:?}",
+                store
+            );
+            assert!(cached_response.body.is_empty());
+        }
+    }
+
+    #[test]
+    fn cache_entry_preserves_status_and_headers() {
+        let response =
+            HttpResponseBuilder::default(ResponseCode::Success(SuccessCode::PartialContent))
+                .header("content-type", "application/octet-stream")
+                .header("content-range", "bytes 0-3/10")
+                .body(&[0, 159, 146, 150])
+                .build();
+        let cache_control = Some("max-age=60".parse().unwrap());
+        for store in test_stores("entry", DEFAULT_MAX_CACHE_SIZE) {
+            store.add("/partial", &response, &cache_control).unwrap();
+            let cached_response = store.retrieve("/partial").unwrap();
+
+            assert_eq!(cached_response.version, "1.1");
+            assert_eq!(cached_response.status_code, 206);
+            assert_eq!(cached_response.reason, "Partial Content");
+            assert_eq!(
+                cached_response.headers.get("content-range").unwrap(),
+                "bytes 0-3/10"
+            );
+            assert_eq!(cached_response.body, [0, 159, 146, 150]);
+            assert_eq!(cached_response.max_age, Some(60));
+            assert!(cached_response.age() < Duration::from_secs(60));
+            assert!(cached_response
+                .as_bytes()
+                .starts_with(b"HTTP/1.1 206 Partial Content\r\n"));
+        }
+
+        let entry = CachedResponse::new(&response, None);
+        let serialized_entry = serde_json::to_string(&entry).unwrap();
+        assert!(serialized_entry.contains("\"body\":\"AJ+Slg==\""));
+        assert_eq!(
+            serde_json::from_str::<CachedResponse>(&serialized_entry).unwrap(),
+            entry
+        );
+    }
+
+    #[test]
+    fn cache_no_store() {
+        let cache_control = Some("no-store".parse().unwrap());
+        for store in test_stores("no_store", DEFAULT_MAX_CACHE_SIZE) {
+            store
+                .add("/no-store", &ok_response(), &cache_control)
+                .unwrap();
+            assert!(store.retrieve("/no-store").is_err(), "{:?}", store);
+        }
+        let cache = test_disk_cache("no_store_directory", DEFAULT_MAX_CACHE_SIZE);
+        cache
+            .add("/no-store", &ok_response(), &cache_control)
+            .unwrap();
+        assert!(!cache.directory.exists());
+    }
+
+    #[test]
+    fn cache_expired_entry() {
+        let cache_control = Some("max-age=0".parse().unwrap());
+        for store in test_stores("expired", DEFAULT_MAX_CACHE_SIZE) {
+            store
+                .add("/expired", &ok_response(), &cache_control)
+                .unwrap();
+            assert!(store.retrieve("/expired").is_err(), "{:?}", store);
+        }
+    }
+
+    #[test]
+    fn cache_fresh_entry() {
+        let cache_control = Some("max-age=60".parse().unwrap());
+        for store in test_stores("fresh", DEFAULT_MAX_CACHE_SIZE) {
+            store.add("/fresh", &ok_response(), &cache_control).unwrap();
+            assert!(store.retrieve("/fresh").is_ok(), "{:?}", store);
+
+            // Without max-age the entry doesn't expire
+            store.add("/no-max-age", &ok_response(), &None).unwrap();
+            assert!(store.retrieve("/no-max-age").is_ok(), "{:?}", store);
+        }
+    }
+
+    #[test]
+    fn cache_evict_least_recently_used() {
+        let stores: Vec<Box<dyn ResponseStore>> = vec![
+            Box::new(test_disk_cache("lru", disk_entry_size() * 3)),
+            Box::new(MemoryCache::new(memory_entry_size() * 3)),
+        ];
+        for store in stores {
+            for resource in ["/first", "/second", "/third"] {
+                store.add(resource, &ok_response(), &None).unwrap();
+            }
+            // `/first` becomes the most recently used one
+            assert!(store.retrieve("/first").is_ok());
+            store.add("/fourth", &ok_response(), &None).unwrap();
+
+            assert!(store.retrieve("/second").is_err(), "{:?}", store);
+            for resource in ["/first", "/third", "/fourth"] {
+                assert!(store.retrieve(resource).is_ok(), "{:?} {}", store, resource);
+            }
+        }
+    }
+
+    #[test]
+    fn cache_configured_directory() {
+        let cache = test_disk_cache("configured_directory", DEFAULT_MAX_CACHE_SIZE);
+        cache.add("/configured", &ok_response(), &None).unwrap();
+
+        let resource_path = cache.get_resource_path("/configured");
+        assert!(resource_path.starts_with(cache.directory()));
+        assert!(resource_path.exists());
+        let default_path = Path::new(PATH_TO_CACHE).join(resource_path.file_name().unwrap());
+        assert!(!default_path.exists());
+        assert!(cache.retrieve("/configured").is_ok());
+    }
+
+    #[test]
+    fn cache_index_restored_from_directory() {
+        let cache = test_disk_cache("restore", DEFAULT_MAX_CACHE_SIZE);
+        let entry_size = disk_entry_size();
+        for resource in ["/first", "/second"] {
+            cache.add(resource, &ok_response(), &None).unwrap();
+        }
+
+        // A smaller cap on the next start evicts entries left by the previous run
+        let cache = DiskCache::new(cache.directory.clone(), entry_size);
+        assert_eq!(cache.lock_index().entries.len(), 1);
+        assert_eq!(cache.lock_index().total_size, entry_size);
+        assert!(!cache.get_resource_path("/first").exists());
+    }
+
+    #[test]
+    fn cache_invalidate_resource() {
+        for cache in test_stores("invalidate", DEFAULT_MAX_CACHE_SIZE) {
+            let invalidated = ["GET /a.txt", "HEAD /a.txt", "GET /a.txt encoding=gzip"];
+            for key in invalidated.iter().chain(&["GET /a.txt.gz", "GET /b/a.txt"]) {
+                cache.add(key, &ok_response(), &None).unwrap();
+            }
+
+            cache.invalidate("/a.txt").unwrap();
+            for key in invalidated {
+                assert!(cache.retrieve(key).is_err());
+            }
+            assert!(cache.retrieve("GET /a.txt.gz").is_ok());
+            assert!(cache.retrieve("GET /b/a.txt").is_ok());
+        }
+
+        // Entries left by a previous run are found as well
+        let cache = test_disk_cache("invalidate_restored", DEFAULT_MAX_CACHE_SIZE);
+        cache.add("GET /a.txt", &ok_response(), &None).unwrap();
+        let cache = DiskCache::new(cache.directory.clone(), DEFAULT_MAX_CACHE_SIZE);
+        cache.invalidate("/a.txt").unwrap();
+        assert!(!cache.get_resource_path("GET /a.txt").exists());
+        assert_eq!(cache.lock_index().total_size, 0);
+    }
+
+    #[test]
+    fn cache_ignores_foreign_files() {
+        let cache = test_disk_cache("foreign_files", DEFAULT_MAX_CACHE_SIZE);
+        cache.add("/cached", &ok_response(), &None).unwrap();
+        let foreign_files = ["notes", "12345", "12345.json", "report.cached"]
+            .map(|name| cache.directory().join(name));
+        for path in &foreign_files {
+            fs::write(path, "not a cache entry").unwrap();
+        }
+
+        // Nothing fits, yet only the entry written by the cache is removed
+        let cache = DiskCache::new(cache.directory.clone(), 0);
+        assert!(cache.lock_index().entries.is_empty());
+        assert!(!cache.get_resource_path("/cached").exists());
+        for path in &foreign_files {
+            assert!(path.exists());
+        }
     }
 }
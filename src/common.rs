@@ -1,7 +1,10 @@
 use std::{
+    cmp,
     collections::HashMap,
-    io::{Cursor, Read, Write},
+    fmt,
+    io::{self, Cursor, Read, Write},
     net::TcpStream,
+    path::Path,
     str::FromStr,
     time::Duration,
 };
@@ -13,9 +16,14 @@ use thiserror::Error;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SuccessCode {
+    SwitchingProtocols = 101,
     Ok = 200,
     Created = 201,
+    NoContent = 204,
     PartialContent = 206,
+    MovedPermanently = 301,
+    Found = 302,
+    NotModified = 304,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -23,17 +31,25 @@ pub enum ErrorCode {
     // Client Errors
     BadRequest = 400,
     Unauthorized = 401,
+    Forbidden = 403,
     NotFound = 404,
+    MethodNotAllowed = 405,
     NotAcceptable = 406,
     RequestTimeout = 408,
+    Conflict = 409,
+    LengthRequired = 411,
     ContentTooLarge = 413,
     URITooLong = 414,
     UnsupportedMediaType = 415,
+    RangeNotSatisfiable = 416,
+    ExpectationFailed = 417,
+    TooManyRequests = 429,
     RequestHeaderFieldsTooLarge = 431,
 
     // Server Errors
     InternalServerError = 500,
     NotImplemented = 501,
+    ServiceUnavailable = 503,
     HTTPVersionNotSupported = 505,
 }
 
@@ -66,26 +82,44 @@ pub enum InternalHttpError {
 }
 
 pub const MAX_HEADERS_AMOUNT: usize = 10_000;
-pub const MAX_REQUEST_BODY_SIZE: u64 = u64::MAX / 2; // 2 GB
+pub const MAX_REQUEST_BODY_SIZE: u64 = 2 * 1024 * 1024 * 1024; // 2 GB
+
+// Of bodies that are held in memory, rather than streamed to disk
+pub const MAX_BUFFERED_BODY_SIZE: u64 = 64 * 1024 * 1024; // 64 MB
 pub const MAX_HEADER_SIZE: u64 = (u16::MAX / 2) as u64; // 8 KB
 pub const DEFAULT_HTTP_VERSION: &str = "1.1";
 pub const MAX_URI_LENGTH: usize = u16::MAX as usize;
+// Of a single `Range` header, many small ranges would make the response much larger than the file
+pub const MAX_RANGES: usize = 100;
 pub const REQUEST_TIMEOUT: Duration = Duration::new(60, 0);
+pub const KEEP_ALIVE_TIMEOUT: Duration = Duration::new(5, 0);
+// IMF-fixdate, like `Sun, 06 Nov 1994 08:49:37 GMT`
+pub const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+pub const CHUNK_SIZE: usize = 8 * 1024; // 8 KB
 
 pub trait HttpStream: Read + Write + Send + 'static {
     fn clone_stream(&self) -> Self;
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
 }
 
 impl HttpStream for TcpStream {
     fn clone_stream(&self) -> Self {
         self.try_clone().expect("Failed to clone stream")
     }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
 }
 
 impl HttpStream for Cursor<Vec<u8>> {
     fn clone_stream(&self) -> Self {
         self.clone()
     }
+
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -94,9 +128,16 @@ pub struct HttpMessageContent {
     body: Vec<u8>,
 }
 
-fn determine_content_type(resource: &str) -> Option<mime_guess::Mime> {
+// Configured types take precedence over the guessed ones, extensions are matched case-insensitively
+fn determine_content_type(resource: &str, mime_types: &HashMap<String, String>) -> Option<String> {
+    let extension = Path::new(resource)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    if let Some(mime_type) = extension.and_then(|extension| mime_types.get(&extension)) {
+        return Some(mime_type.clone());
+    }
     let mime_type = from_path(resource);
-    mime_type.first()
+    mime_type.first().map(|mime_type| mime_type.to_string())
 }
 
 impl HttpMessageContent {
@@ -113,8 +154,15 @@ impl HttpMessageContent {
         header_name: impl Into<String>,
         header_content: impl Into<String>,
     ) -> Option<String> {
+        self.headers.insert(
+            header_name.into().to_ascii_lowercase(),
+            header_content.into(),
+        )
+    }
+
+    pub fn remove_header(&mut self, header_name: impl Into<String>) -> Option<String> {
         self.headers
-            .insert(header_name.into(), header_content.into())
+            .remove(&header_name.into().to_ascii_lowercase())
     }
 
     pub fn get_body(&self) -> &Vec<u8> {
@@ -129,21 +177,25 @@ impl HttpMessageContent {
         &self.headers
     }
 
-    pub fn get_content_type(&self, path_to_resource: &str) -> Result<String> {
+    pub fn get_content_type(
+        &self,
+        path_to_resource: &str,
+        mime_types: &HashMap<String, String>,
+    ) -> Result<String> {
         if let Some(content_type) = self.headers.get("content-type") {
             // TODO: verify this, at it might not be supported by the server
             Ok(content_type.clone())
         } else {
             trace!("Content type wasn't provided by the client, determine content type based on the resource name");
-            let mime_type = determine_content_type(&path_to_resource)
-                .ok_or_else(|| anyhow!("Failed to determine MIME type"))?
-                .to_string();
+            let mime_type = determine_content_type(path_to_resource, mime_types)
+                .ok_or_else(|| anyhow!("Failed to determine MIME type"))?;
             Ok(mime_type)
         }
     }
 }
 
-#[derive(Debug, Clone)]
+// Both ends are inclusive, `0-0` is the first byte
+#[derive(Debug, Clone, PartialEq)]
 pub struct Range {
     pub from: u64,
     pub to: u64,
@@ -164,72 +216,159 @@ impl FromStr for Range {
             let from = from.parse().ok()?;
             let to = to.parse().ok()?;
 
-            if from < to {
+            if from <= to {
                 Some(Range::new(from, to))
             } else {
                 None
             }
         }
-        parse_range(&range).ok_or_else(|| anyhow!(format!("Failed to parse range: {}", range)))
+        parse_range(range).ok_or_else(|| anyhow!(format!("Failed to parse range: {}", range)))
     }
 }
 
-impl ToString for Range {
-    fn to_string(&self) -> String {
-        format!("{}-{}", self.from, self.to)
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.from, self.to)
+    }
+}
+
+// Range as requested by the client, resolved once the size of the resource is known
+#[derive(Debug, Clone, PartialEq)]
+pub enum RangeSpec {
+    // `100-150`
+    Bounded(Range),
+    // `500-`, up to the end of the resource
+    StartingAt(u64),
+    // `-500`, the last 500 bytes
+    Suffix(u64),
+}
+
+impl RangeSpec {
+    // `None` when the range starts past the end of the resource, the end is clamped to it
+    pub fn resolve(&self, resource_len: u64) -> Option<Range> {
+        let last_byte = resource_len.saturating_sub(1);
+        let range = match self {
+            RangeSpec::Bounded(range) => Range::new(range.from, cmp::min(range.to, last_byte)),
+            RangeSpec::StartingAt(from) => Range::new(*from, last_byte),
+            RangeSpec::Suffix(suffix_len) => {
+                Range::new(resource_len.saturating_sub(*suffix_len), last_byte)
+            }
+        };
+        (range.from < resource_len).then_some(range)
+    }
+}
+
+impl From<Range> for RangeSpec {
+    fn from(range: Range) -> Self {
+        RangeSpec::Bounded(range)
+    }
+}
+
+impl FromStr for RangeSpec {
+    type Err = anyhow::Error;
+    // Example: 100-150, 500- or -500
+    fn from_str(range: &str) -> Result<Self> {
+        if let Some(from) = range.strip_suffix('-') {
+            return from
+                .parse()
+                .map(RangeSpec::StartingAt)
+                .map_err(|_| anyhow!(format!("Failed to parse open-ended range: {}", range)));
+        }
+        // `-0` is valid, it just can't be satisfied
+        match range.strip_prefix('-') {
+            Some(suffix_len) => suffix_len
+                .parse()
+                .map(RangeSpec::Suffix)
+                .map_err(|_| anyhow!(format!("Failed to parse suffix range: {}", range))),
+            None => Ok(RangeSpec::Bounded(range.parse()?)),
+        }
+    }
+}
+
+impl fmt::Display for RangeSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RangeSpec::Bounded(range) => write!(f, "{}", range),
+            RangeSpec::StartingAt(from) => write!(f, "{}-", from),
+            RangeSpec::Suffix(suffix_len) => write!(f, "-{}", suffix_len),
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Ranges {
-    ranges: Vec<Range>,
+    ranges: Vec<RangeSpec>,
 }
 
 impl Ranges {
-    pub fn new(ranges: Vec<Range>) -> Self {
+    pub fn new(ranges: Vec<RangeSpec>) -> Self {
         Self { ranges }
     }
 
-    pub fn is_multipart(&self) -> bool {
-        self.ranges.len() > 1
+    pub fn len(&self) -> usize {
+        self.ranges.len()
     }
 
-    pub fn first(&self) -> Option<&Range> {
-        self.ranges.first()
+    // Byte ranges are the only ones the server understands, like in `bytes=0-50`
+    pub fn is_bytes_unit(unit: &str) -> bool {
+        unit.trim().eq_ignore_ascii_case("bytes")
     }
 
-    pub fn len(&self) -> usize {
-        self.ranges.len()
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
     }
 
-    pub fn elements(&self) -> &Vec<Range> {
-        &self.ranges
+    // Unsatisfiable ranges are skipped, the request can't be served if none are left. The rest are
+    // sorted, overlapping and adjacent ones are merged, so no byte is sent twice
+    pub fn resolve(&self, resource_len: u64) -> Vec<Range> {
+        let mut ranges: Vec<Range> = self
+            .ranges
+            .iter()
+            .filter_map(|range| range.resolve(resource_len))
+            .collect();
+        ranges.sort_by_key(|range| range.from);
+
+        let mut merged_ranges: Vec<Range> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged_ranges.last_mut() {
+                Some(last) if range.from <= last.to.saturating_add(1) => {
+                    last.to = cmp::max(last.to, range.to);
+                }
+                _ => merged_ranges.push(range),
+            }
+        }
+        merged_ranges
     }
 }
 
 impl FromStr for Ranges {
     type Err = anyhow::Error;
-    // Example: bytes=0-50, 100-150"
+    // Example: bytes=0-50, 100-150, 200-, -20"
     fn from_str(ranges: &str) -> Result<Self> {
         fn parse_ranges(data: &str) -> Option<Ranges> {
-            let ranges = data.strip_prefix("bytes=")?;
+            let ranges = data
+                .split_once('=')
+                .filter(|(unit, _)| Ranges::is_bytes_unit(unit))?
+                .1;
             let res = ranges
                 .split(',')
                 .map(|range| range.trim().parse().ok())
-                .collect::<Option<Vec<Range>>>()?;
+                .collect::<Option<Vec<RangeSpec>>>()?;
             Some(Ranges::new(res))
         }
-        parse_ranges(&ranges)
+        parse_ranges(ranges)
             .ok_or_else(|| anyhow!(format!("Failed to parse multipart range: {}", ranges)))
     }
 }
 
-impl ToString for Ranges {
-    fn to_string(&self) -> String {
-        self.ranges
+impl fmt::Display for Ranges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ranges = self
+            .ranges
             .iter()
             .map(ToString::to_string)
             .collect::<Vec<_>>()
-            .join(",")
+            .join(",");
+        write!(f, "{}", ranges)
     }
 }
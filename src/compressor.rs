@@ -1,9 +1,9 @@
-use anyhow::anyhow;
+use anyhow::{anyhow, Context, Result};
 use flate2::{
-    read::{DeflateEncoder, GzEncoder},
+    read::{DeflateDecoder, DeflateEncoder, GzDecoder, GzEncoder},
     Compression,
 };
-use std::{io::Read, str::FromStr};
+use std::{fmt, io::Read, str::FromStr};
 
 // https://www.iana.org/assignments/http-parameters/http-parameters.xhtml#content-coding
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -19,28 +19,44 @@ pub enum ContentEncoding {
     Zstd,
 }
 
-static SUPPORTED_ENCODINGS: [ContentEncoding; 2] =
-    [ContentEncoding::Gzip, ContentEncoding::Identity];
+static SUPPORTED_ENCODINGS: [ContentEncoding; 5] = [
+    ContentEncoding::Br,
+    ContentEncoding::Deflate,
+    ContentEncoding::Gzip,
+    ContentEncoding::Identity,
+    ContentEncoding::Zstd,
+];
 pub const DEFAULT_ENCODING: ContentEncoding = ContentEncoding::Identity;
+pub const MIN_COMPRESSION_SIZE: usize = 256;
 
 impl ContentEncoding {
     pub fn is_supported(&self) -> bool {
         SUPPORTED_ENCODINGS.contains(self)
     }
+
+    // Extension of files compressed ahead of time, like `style.css.gz`
+    pub fn file_extension(&self) -> Option<&'static str> {
+        match self {
+            ContentEncoding::Br => Some("br"),
+            ContentEncoding::Gzip => Some("gz"),
+            ContentEncoding::Zstd => Some("zst"),
+            _ => None,
+        }
+    }
 }
 
-impl ToString for ContentEncoding {
-    fn to_string(&self) -> String {
+impl fmt::Display for ContentEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ContentEncoding::Pack200gzip => "pack200-gzip".to_string(),
-            _ => format!("{:?}", self).to_lowercase(),
+            ContentEncoding::Pack200gzip => write!(f, "pack200-gzip"),
+            _ => write!(f, "{}", format!("{:?}", self).to_lowercase()),
         }
     }
 }
 
 impl FromStr for ContentEncoding {
     type Err = anyhow::Error;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+    fn from_str(s: &str) -> Result<Self> {
         if s.starts_with("*") {
             Ok(DEFAULT_ENCODING)
         } else {
@@ -60,23 +76,163 @@ impl FromStr for ContentEncoding {
     }
 }
 
+const BROTLI_BUFFER_SIZE: usize = 4096;
+// Favor speed over ratio, same as `Compression::fast()` for gzip
+const BROTLI_QUALITY: u32 = 4;
+const BROTLI_WINDOW_SIZE: u32 = 22;
+const ZSTD_LEVEL: i32 = 3;
+
+// Media types that are already compressed, compressing them again only wastes CPU
+static INCOMPRESSIBLE_MEDIA_TYPES: [&str; 13] = [
+    "image/",
+    "video/",
+    "audio/",
+    "font/woff",
+    "application/zip",
+    "application/gzip",
+    "application/x-gzip",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/x-7z-compressed",
+    "application/x-rar-compressed",
+    "application/zstd",
+    "application/wasm",
+];
+
 pub struct Compressor {}
 impl Compressor {
-    pub fn compress(data: &[u8], content_encoding: ContentEncoding) -> Vec<u8> {
-        fn compress_internal<T: Read>(mut compressor: T) -> Vec<u8> {
+    pub fn is_compressible(content_type: &str) -> bool {
+        let content_type = content_type.trim().to_ascii_lowercase();
+        // SVG is plain XML, unlike the rest of the images
+        content_type.starts_with("image/svg+xml")
+            || !INCOMPRESSIBLE_MEDIA_TYPES
+                .iter()
+                .any(|media_type| content_type.starts_with(media_type))
+    }
+
+    pub fn compress(data: &[u8], content_encoding: ContentEncoding) -> Result<Vec<u8>> {
+        fn compress_internal<T: Read>(mut compressor: T) -> Result<Vec<u8>> {
             let mut ret_vec = Vec::new();
             compressor
                 .read_to_end(&mut ret_vec)
-                .expect("Failed to compress");
-            ret_vec
+                .context("Failed to compress")?;
+            Ok(ret_vec)
         }
         match content_encoding {
+            ContentEncoding::Br => compress_internal(brotli::CompressorReader::new(
+                data,
+                BROTLI_BUFFER_SIZE,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW_SIZE,
+            )),
             ContentEncoding::Gzip => compress_internal(GzEncoder::new(data, Compression::fast())),
             ContentEncoding::Deflate => {
                 compress_internal(DeflateEncoder::new(data, Compression::fast()))
             }
-            ContentEncoding::Identity => Vec::from(data),
-            _ => panic!("Unsupported content encoding {:?}", content_encoding),
+            ContentEncoding::Zstd => {
+                compress_internal(zstd::stream::read::Encoder::new(data, ZSTD_LEVEL)?)
+            }
+            ContentEncoding::Identity => Ok(Vec::from(data)),
+            _ => Err(anyhow!(
+                "Unsupported content encoding {:?}",
+                content_encoding
+            )),
+        }
+    }
+
+    pub fn decompress(data: &[u8], content_encoding: ContentEncoding) -> Result<Vec<u8>> {
+        Compressor::decompress_limited(data, content_encoding, u64::MAX - 1)
+    }
+
+    // Stops after `max_len + 1` bytes, a longer result means the data inflates past the limit
+    pub fn decompress_limited(
+        data: &[u8],
+        content_encoding: ContentEncoding,
+        max_len: u64,
+    ) -> Result<Vec<u8>> {
+        let decompress_internal = |decompressor: &mut dyn Read| -> Result<Vec<u8>> {
+            let mut ret_vec = Vec::new();
+            decompressor
+                .take(max_len + 1)
+                .read_to_end(&mut ret_vec)
+                .context("Failed to decompress")?;
+            Ok(ret_vec)
+        };
+        match content_encoding {
+            ContentEncoding::Br => {
+                decompress_internal(&mut brotli::Decompressor::new(data, BROTLI_BUFFER_SIZE))
+            }
+            ContentEncoding::Gzip => decompress_internal(&mut GzDecoder::new(data)),
+            ContentEncoding::Deflate => decompress_internal(&mut DeflateDecoder::new(data)),
+            ContentEncoding::Zstd => {
+                decompress_internal(&mut zstd::stream::read::Decoder::new(data)?)
+            }
+            ContentEncoding::Identity => decompress_internal(&mut &data[..]),
+            _ => Err(anyhow!(
+                "Unsupported content encoding {:?}",
+                content_encoding
+            )),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_DATA: &[u8] = b"The quick brown fox jumps over the lazy dog. \
+                               The quick brown fox jumps over the lazy dog.";
+
+    #[test]
+    fn compress_br_round_trip() {
+        assert!(ContentEncoding::Br.is_supported());
+
+        let compressed = Compressor::compress(TEST_DATA, ContentEncoding::Br).unwrap();
+        let mut decompressed = Vec::new();
+        brotli::Decompressor::new(compressed.as_slice(), BROTLI_BUFFER_SIZE)
+            .read_to_end(&mut decompressed)
+            .expect("Failed to decompress brotli data");
+
+        assert_eq!(decompressed, TEST_DATA);
+    }
+
+    #[test]
+    fn compress_zstd_round_trip() {
+        assert!(ContentEncoding::Zstd.is_supported());
+
+        let compressed = Compressor::compress(TEST_DATA, ContentEncoding::Zstd).unwrap();
+        let decompressed =
+            zstd::stream::decode_all(compressed.as_slice()).expect("Invalid zstd frame");
+
+        assert_eq!(decompressed, TEST_DATA);
+    }
+
+    #[test]
+    fn compress_unsupported_encoding() {
+        assert!(Compressor::compress(TEST_DATA, ContentEncoding::Pack200gzip).is_err());
+    }
+
+    #[test]
+    fn decompress_round_trip() {
+        for content_encoding in SUPPORTED_ENCODINGS {
+            let compressed = Compressor::compress(TEST_DATA, content_encoding).unwrap();
+            let decompressed = Compressor::decompress(&compressed, content_encoding).unwrap();
+            assert_eq!(decompressed, TEST_DATA);
+        }
+    }
+
+    #[test]
+    fn decompress_invalid_data() {
+        assert!(Compressor::decompress(TEST_DATA, ContentEncoding::Gzip).is_err());
+    }
+
+    #[test]
+    fn compressible_content_types() {
+        assert!(Compressor::is_compressible("text/plain"));
+        assert!(Compressor::is_compressible("image/svg+xml"));
+        assert!(Compressor::is_compressible("application/json"));
+        assert!(!Compressor::is_compressible("image/png"));
+        assert!(!Compressor::is_compressible("video/mp4"));
+        assert!(!Compressor::is_compressible("application/zip"));
+    }
+}
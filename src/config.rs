@@ -0,0 +1,359 @@
+use std::{
+    collections::HashMap,
+    fs, iter,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    auth::Authenticator,
+    cache::{DiskCache, ResponseStore},
+    common::{MAX_BUFFERED_BODY_SIZE, MAX_HEADER_SIZE, MAX_REQUEST_BODY_SIZE, REQUEST_TIMEOUT},
+    middleware::MiddlewareChain,
+    rate_limit::RateLimiter,
+    router::Router,
+    shutdown::ShutdownSignal,
+};
+
+pub const DEFAULT_INDEX_FILE: &str = "index.html";
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = REQUEST_TIMEOUT;
+pub const DEFAULT_CONTENT_SECURITY_POLICY: &str = "default-src 'self'";
+pub const DEFAULT_SOCKET_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_SERVER_HEADER: &str = "simple http";
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub authenticator: Authenticator,
+    // Shared by all connections
+    pub cache: Arc<dyn ResponseStore>,
+    // Path prefixes, like `/private`, that can't be accessed without credentials
+    pub protected_paths: Vec<String>,
+    // Answer directory requests with an HTML index of their entries, rather than 404
+    pub directory_listing: bool,
+    // File served in place of a directory that contains it, takes precedence over the listing
+    pub index_file: String,
+    // Files are served from and written to this directory only
+    pub root: PathBuf,
+    // Searched in order for files that aren't under the root, e.g. shared assets.
+    // Uploads always go to the root
+    pub fallback_roots: Vec<PathBuf>,
+    // Symlinks under the root are followed, even out of it. Otherwise paths through them are
+    // forbidden
+    pub follow_symlinks: bool,
+    // Host name, lowercase and without the port, to the directory its files are served from
+    pub virtual_hosts: HashMap<String, PathBuf>,
+    // Requests for hosts that aren't in `virtual_hosts` get 404, rather than files under `root`
+    pub strict_virtual_hosts: bool,
+    // Names of hidden files and directories, like `.well-known`, that can be accessed anyway.
+    // Every other path with a component that starts with a dot is forbidden
+    pub allowed_hidden_files: Vec<String>,
+    // Time a client has to send the whole request, including the body
+    pub request_timeout: Duration,
+    // Longest a single read or write on the socket may block, `None` waits forever
+    pub read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+    // Of a single header line, in bytes
+    pub max_header_size: u64,
+    pub max_body_size: u64,
+    // Of bodies that have to be held in memory, uploads written to disk are only held to
+    // `max_body_size`
+    pub max_buffered_body_size: u64,
+    // Origins allowed to make cross-origin requests, `*` allows any of them. Empty disables CORS
+    pub cors_allowed_origins: Vec<String>,
+    // `X-Content-Type-Options`, `X-Frame-Options` and `Content-Security-Policy` on every response
+    pub security_headers: bool,
+    pub content_security_policy: String,
+    // Value of the `Server` header, `None` leaves it out
+    pub server_header: Option<String>,
+    // `Content-Language` of responses that don't set their own
+    pub content_language: Option<String>,
+    // Shared by all connections, requests aren't limited without it
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    // `Cache-Control: max-age` and `Expires` of served files, they aren't sent without it
+    pub max_age: Option<Duration>,
+    // Extension, without the dot and lowercase, to MIME type. Consulted before the guessed types
+    pub mime_types: HashMap<String, String>,
+    // Consulted before the built-in behavior
+    pub router: Router,
+    // Wraps the handling of every request, including the routes
+    pub middleware: MiddlewareChain,
+    // Shared with whoever stops the server, see `ShutdownSignal`
+    pub shutdown: ShutdownSignal,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            authenticator: Authenticator::default(),
+            cache: Arc::new(DiskCache::default()),
+            protected_paths: Vec::new(),
+            directory_listing: false,
+            index_file: String::from(DEFAULT_INDEX_FILE),
+            root: PathBuf::from("."),
+            fallback_roots: Vec::new(),
+            follow_symlinks: false,
+            virtual_hosts: HashMap::new(),
+            strict_virtual_hosts: false,
+            allowed_hidden_files: Vec::new(),
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            read_timeout: Some(DEFAULT_SOCKET_TIMEOUT),
+            write_timeout: Some(DEFAULT_SOCKET_TIMEOUT),
+            max_header_size: MAX_HEADER_SIZE,
+            max_body_size: MAX_REQUEST_BODY_SIZE,
+            max_buffered_body_size: MAX_BUFFERED_BODY_SIZE,
+            cors_allowed_origins: Vec::new(),
+            security_headers: true,
+            content_security_policy: String::from(DEFAULT_CONTENT_SECURITY_POLICY),
+            server_header: Some(String::from(DEFAULT_SERVER_HEADER)),
+            content_language: None,
+            rate_limiter: None,
+            max_age: None,
+            mime_types: HashMap::new(),
+            router: Router::default(),
+            middleware: MiddlewareChain::default(),
+            shutdown: ShutdownSignal::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    // Segments are split the way the path of the file is built, backslashes included, so
+    // `/\private` and `//private` are protected like `/private`
+    pub fn is_protected(&self, resource: &str) -> bool {
+        let resource = path_segments(resource);
+        self.protected_paths.iter().any(|protected_path| {
+            // Match whole path segments only, `/private` doesn't protect `/privateer`
+            resource.starts_with(&path_segments(protected_path))
+        })
+    }
+
+    // Dotfiles like `.env` or `.git` are usually not meant to be served
+    pub fn is_hidden(&self, resource: &str) -> bool {
+        resource.split(['/', '\\']).any(|component| {
+            component.starts_with('.')
+                && component != "."
+                && component != ".."
+                && !self
+                    .allowed_hidden_files
+                    .iter()
+                    .any(|allowed| allowed == component)
+        })
+    }
+
+    // Value of `Access-Control-Allow-Origin` for the request origin, if it's allowed
+    pub fn allowed_origin(&self, origin: &str) -> Option<String> {
+        if self
+            .cors_allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*")
+        {
+            Some(String::from("*"))
+        } else {
+            self.cors_allowed_origins
+                .iter()
+                .find(|allowed| allowed.trim_end_matches('/') == origin)
+                .cloned()
+        }
+    }
+
+    // Directory the files of the host are served from, `None` if the host can't be served
+    pub fn root_for_host(&self, host: Option<&str>) -> Option<&PathBuf> {
+        match host.and_then(|host| self.virtual_hosts.get(host)) {
+            Some(root) => Some(root),
+            None if self.strict_virtual_hosts && !self.virtual_hosts.is_empty() => None,
+            None => Some(&self.root),
+        }
+    }
+
+    pub fn is_under_root(&self, path: &Path) -> bool {
+        match (self.root.canonicalize(), path.canonicalize()) {
+            (Ok(root), Ok(path)) => path.starts_with(root),
+            _ => false,
+        }
+    }
+
+    pub fn resolve_path(&self, resource: &str) -> Option<PathBuf> {
+        resolve_under(&self.root, resource, self.follow_symlinks)
+    }
+
+    // Files that are symlinks themselves, like the index file of a directory
+    pub fn is_forbidden_symlink(&self, path: &Path) -> bool {
+        !self.follow_symlinks
+            && fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink())
+    }
+
+    // First of `root` and the fallback roots that has the resource, `root` when none has it
+    pub fn root_with_resource<'config>(
+        &'config self,
+        root: &'config PathBuf,
+        resource: &str,
+    ) -> &'config PathBuf {
+        iter::once(root)
+            .chain(&self.fallback_roots)
+            .find(|root| {
+                resolve_under(root, resource, self.follow_symlinks)
+                    .is_some_and(|path| path.exists())
+            })
+            .unwrap_or(root)
+    }
+}
+
+pub(crate) fn path_segments(path: &str) -> Vec<&str> {
+    path.split(['/', '\\'])
+        .filter(|segment| !segment.is_empty() && *segment != ".")
+        .collect()
+}
+
+// Whether one of the components of `relative` under the root is a symlink. Components after a
+// missing one can't be
+fn has_symlink(root: &Path, relative: &Path) -> bool {
+    let mut path = root.to_path_buf();
+    for component in relative.components() {
+        path.push(component);
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => return true,
+            Ok(_) => {}
+            Err(_) => return false,
+        }
+    }
+    false
+}
+
+// Path of the resource under the root, `None` if it escapes the root or goes through a symlink
+// that isn't followed. Followed symlinks may lead anywhere.
+// Files that don't exist yet are checked through their closest existing ancestor
+fn resolve_under(root: &Path, resource: &str, follow_symlinks: bool) -> Option<PathBuf> {
+    let root = root.canonicalize().ok()?;
+    let relative = Path::new(resource.trim_start_matches(['/', '\\']));
+    if relative
+        .components()
+        .any(|component| component == Component::ParentDir)
+        || (!follow_symlinks && has_symlink(&root, relative))
+    {
+        return None;
+    }
+    let path = root.join(relative);
+
+    let mut missing_components = Vec::new();
+    let mut existing_ancestor = path.as_path();
+    let resolved = loop {
+        if let Ok(canonical_ancestor) = existing_ancestor.canonicalize() {
+            break missing_components
+                .into_iter()
+                .rev()
+                .fold(canonical_ancestor, |path, component| path.join(component));
+        }
+        let file_name = existing_ancestor.file_name()?;
+        missing_components.push(Path::new(file_name).to_path_buf());
+        existing_ancestor = existing_ancestor.parent()?;
+    };
+
+    let escapes_root = (!follow_symlinks && !resolved.starts_with(&root))
+        || resolved
+            .components()
+            .any(|component| component == Component::ParentDir);
+    (!escapes_root).then_some(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protected_paths() {
+        let config = ServerConfig {
+            protected_paths: vec![String::from("/private"), String::from("docs/internal/")],
+            ..Default::default()
+        };
+
+        assert!(config.is_protected("/private"));
+        assert!(config.is_protected("/private/file.txt"));
+        assert!(config.is_protected("/docs/internal/file.txt"));
+        assert!(!config.is_protected("/privateer"));
+        assert!(!config.is_protected("/docs/file.txt"));
+        assert!(!config.is_protected("/"));
+        assert!(config.is_protected("/\\private/file.txt"));
+        assert!(config.is_protected("//docs/./internal"));
+
+        let config = ServerConfig {
+            protected_paths: vec![String::from("/")],
+            ..Default::default()
+        };
+        assert!(config.is_protected("/file.txt"));
+    }
+
+    #[test]
+    fn virtual_host_roots() {
+        let mut config = ServerConfig {
+            root: PathBuf::from("public"),
+            virtual_hosts: HashMap::from([(String::from("a.test"), PathBuf::from("sites/a"))]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            config.root_for_host(Some("a.test")).unwrap(),
+            Path::new("sites/a")
+        );
+        assert_eq!(
+            config.root_for_host(Some("b.test")).unwrap(),
+            Path::new("public")
+        );
+        assert_eq!(config.root_for_host(None).unwrap(), Path::new("public"));
+
+        config.strict_virtual_hosts = true;
+        assert!(config.root_for_host(Some("b.test")).is_none());
+        assert!(config.root_for_host(None).is_none());
+    }
+
+    #[test]
+    fn resolve_path() {
+        let root = std::env::temp_dir().join("http_server_root");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::write(root.join("docs").join("file.txt"), "content").unwrap();
+        let config = ServerConfig {
+            root: root.clone(),
+            ..Default::default()
+        };
+        let root = root.canonicalize().unwrap();
+
+        assert_eq!(
+            config.resolve_path("/docs/file.txt").unwrap(),
+            root.join("docs").join("file.txt")
+        );
+        assert_eq!(
+            config.resolve_path("docs/new/file.txt").unwrap(),
+            root.join("docs").join("new").join("file.txt")
+        );
+        assert_eq!(config.resolve_path("").unwrap(), root);
+        assert!(config.resolve_path("../outside.txt").is_none());
+        assert!(config.resolve_path("docs/../../outside.txt").is_none());
+        assert!(config.resolve_path("missing/../../outside.txt").is_none());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(std::env::temp_dir(), root.join("escape")).unwrap();
+            assert!(config.resolve_path("escape/file.txt").is_none());
+            std::os::unix::fs::symlink(root.join("docs"), root.join("docs_link")).unwrap();
+            assert!(config.resolve_path("docs_link/file.txt").is_none());
+
+            let config = ServerConfig {
+                follow_symlinks: true,
+                ..config
+            };
+            assert_eq!(
+                config.resolve_path("docs_link/file.txt").unwrap(),
+                root.join("docs").join("file.txt")
+            );
+            assert_eq!(
+                config.resolve_path("escape/file.txt").unwrap(),
+                std::env::temp_dir()
+                    .canonicalize()
+                    .unwrap()
+                    .join("file.txt")
+            );
+            assert!(config.resolve_path("escape/../outside.txt").is_none());
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+// Counts connections that were accepted but aren't finished yet, including the queued ones
+#[derive(Debug)]
+pub struct ConnectionLimiter {
+    max_connections: usize,
+    in_flight: Arc<AtomicUsize>,
+}
+
+// Releases the slot of the connection once dropped
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl ConnectionLimiter {
+    pub fn new(max_connections: usize) -> Self {
+        Self {
+            max_connections,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    // `None` when the limit is reached
+    pub fn try_acquire(&self) -> Option<ConnectionGuard> {
+        self.in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |in_flight| {
+                (in_flight < self.max_connections).then_some(in_flight + 1)
+            })
+            .ok()?;
+        Some(ConnectionGuard {
+            in_flight: Arc::clone(&self.in_flight),
+        })
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connection_limiter_saturation() {
+        let limiter = ConnectionLimiter::new(2);
+
+        let first = limiter.try_acquire().unwrap();
+        let _second = limiter.try_acquire().unwrap();
+        assert!(limiter.try_acquire().is_none());
+        assert_eq!(limiter.in_flight(), 2);
+
+        drop(first);
+        assert_eq!(limiter.in_flight(), 1);
+        assert!(limiter.try_acquire().is_some());
+    }
+}
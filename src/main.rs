@@ -1,63 +1,362 @@
-pub mod auth;
-pub mod cache;
-pub mod common;
-pub mod compressor;
-pub mod request;
-pub mod response;
-pub mod url;
-
 use std::{
-    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener},
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
     str::FromStr,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
 };
 
-use clap::{arg, Parser};
-use log::{error, info, LevelFilter, Metadata, Record};
+use clap::Parser;
+use http_server::{
+    auth::{Authenticator, CREDENTIALS_ENV_VAR},
+    cache::{DiskCache, MemoryCache, ResponseStore, DEFAULT_MAX_CACHE_SIZE, PATH_TO_CACHE},
+    common::{MAX_BUFFERED_BODY_SIZE, MAX_HEADER_SIZE, MAX_REQUEST_BODY_SIZE},
+    config::{
+        ServerConfig, DEFAULT_CONTENT_SECURITY_POLICY, DEFAULT_INDEX_FILE, DEFAULT_REQUEST_TIMEOUT,
+        DEFAULT_SERVER_HEADER, DEFAULT_SOCKET_TIMEOUT,
+    },
+    connection_limit::ConnectionLimiter,
+    middleware::MiddlewareChain,
+    rate_limit::RateLimiter,
+    request_id,
+    router::Router,
+    shutdown::ShutdownSignal,
+};
+use log::{error, info, warn, LevelFilter, Metadata, Record};
 use threadpool::ThreadPool;
 
-static CONSOLE_LOGGER: ConsoleLogger = ConsoleLogger;
-struct ConsoleLogger;
+// Once the file grows past its maximum size, it's moved to `<path>.1` and a new one is started
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            size,
+            max_size,
+        })
+    }
+
+    fn rotated_path(&self) -> PathBuf {
+        let mut rotated_path = self.path.clone().into_os_string();
+        rotated_path.push(".1");
+        PathBuf::from(rotated_path)
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let line_size = line.len() as u64 + 1;
+        if self.size > 0 && self.size + line_size > self.max_size {
+            fs::rename(&self.path, self.rotated_path())?;
+            *self = RotatingFile::open(self.path.clone(), self.max_size)?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += line_size;
+        Ok(())
+    }
+}
 
-impl log::Log for ConsoleLogger {
+// Writes every record to the console and, when configured, to the log file
+struct Logger {
+    file: Option<Mutex<RotatingFile>>,
+}
+
+impl log::Log for Logger {
     fn enabled(&self, metadata: &Metadata) -> bool {
         metadata.level() <= log::max_level()
     }
 
     fn log(&self, record: &Record) {
-        if self.enabled(record.metadata()) {
-            println!("[{}] {}", record.level(), record.args());
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = match request_id::current() {
+            Some(request_id) => format!("[{}] [{}] {}", record.level(), request_id, record.args()),
+            None => format!("[{}] {}", record.level(), record.args()),
+        };
+        println!("{}", line);
+
+        if let Some(file) = &self.file {
+            let mut file = file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if let Err(err) = file.write_line(&line) {
+                eprintln!("Failed to write to the log file: {:?}", err);
+            }
         }
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            let mut file = file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let _ = file.file.flush();
+        }
+    }
 }
 
-fn default_ip() -> Ipv4Addr {
-    Ipv4Addr::new(127, 0, 0, 1)
+fn load_logger(args: &Args) -> Logger {
+    let file = args.log_file.as_ref().map(|log_file| {
+        let file = RotatingFile::open(log_file.clone(), args.log_file_max_size)
+            .expect("Failed to open the log file");
+        Mutex::new(file)
+    });
+    Logger { file }
+}
+
+fn default_ip() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))
 }
 
 fn default_port() -> u16 {
     4421
 }
 
+// Suggested to clients rejected because of too many connections
+const RETRY_AFTER: Duration = Duration::from_secs(1);
+
+const DEFAULT_LOG_FILE_MAX_SIZE: u64 = 10 * 1024 * 1024; // 10 MB
+
 #[derive(Parser, Debug, Default)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// Ip address of the HTTP server
+    /// Ip address of the HTTP server, either IPv4 or IPv6
     #[arg(short, long, default_value_t = default_ip().to_string())]
     ip: String,
 
+    /// Listen on all IPv4 and IPv6 addresses, `--ip` is ignored
+    #[arg(long)]
+    dual_stack: bool,
+
     /// Port number
     #[arg(short, long, default_value_t = default_port())]
     port: u16,
+
+    /// File with `username:password` pairs, one per line.
+    /// When omitted, credentials are read from the HTTP_SERVER_CREDENTIALS variable
+    #[arg(long)]
+    credentials_file: Option<PathBuf>,
+
+    /// Path prefix that requires authentication, can be repeated
+    #[arg(long = "protected-path")]
+    protected_paths: Vec<String>,
+
+    /// Name of a hidden file or directory, like `.well-known`, that can be accessed anyway.
+    /// Can be repeated, every other path with a component starting with a dot is forbidden
+    #[arg(long = "allow-hidden")]
+    allowed_hidden_files: Vec<String>,
+
+    /// Maximum size of cached responses in bytes, least recently used ones are evicted first
+    #[arg(long, default_value_t = DEFAULT_MAX_CACHE_SIZE)]
+    max_cache_size: u64,
+
+    /// Keep cached responses in memory rather than in the cache directory
+    #[arg(long)]
+    memory_cache: bool,
+
+    /// Directory cached responses are stored in, every server instance should have its own
+    #[arg(long, default_value = PATH_TO_CACHE)]
+    cache_dir: PathBuf,
+
+    /// List the entries of requested directories, disabled by default
+    #[arg(long)]
+    directory_listing: bool,
+
+    /// File served for requests to a directory that contains it
+    #[arg(long, default_value_t = DEFAULT_INDEX_FILE.to_string())]
+    index_file: String,
+
+    /// Directory the files are served from, requests can't reach outside of it
+    #[arg(long, default_value = ".")]
+    root: PathBuf,
+
+    /// Directory searched for files that aren't under the root, can be repeated.
+    /// The directories are searched in order, uploads always go to the root
+    #[arg(long = "fallback-root")]
+    fallback_roots: Vec<PathBuf>,
+
+    /// Follow symlinks under the root, even when they lead out of it.
+    /// Otherwise requests through a symlink are answered with 403
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Directory the files of a host are served from, like `example.com=sites/example`.
+    /// Can be repeated, other hosts are served from the root
+    #[arg(long = "virtual-host", value_parser = parse_virtual_host)]
+    virtual_hosts: Vec<(String, PathBuf)>,
+
+    /// Answer requests for hosts without a virtual host with 404, rather than files from the root
+    #[arg(long)]
+    strict_virtual_hosts: bool,
+
+    /// Seconds clients and caches may reuse a served file, sent as `Cache-Control` and `Expires`
+    #[arg(long)]
+    max_age: Option<u64>,
+
+    /// Seconds a client has to send a whole request, including the body
+    #[arg(long, default_value_t = DEFAULT_REQUEST_TIMEOUT.as_secs())]
+    request_timeout: u64,
+
+    /// Seconds a single read from a client may block, 0 waits forever
+    #[arg(long, default_value_t = DEFAULT_SOCKET_TIMEOUT.as_secs())]
+    read_timeout: u64,
+
+    /// Seconds a single write to a client may block, 0 waits forever
+    #[arg(long, default_value_t = DEFAULT_SOCKET_TIMEOUT.as_secs())]
+    write_timeout: u64,
+
+    /// Maximum size of a single header line in bytes, longer ones are answered with 431
+    #[arg(long, default_value_t = MAX_HEADER_SIZE)]
+    max_header_size: u64,
+
+    /// Maximum size of a request body in bytes, larger ones are answered with 413
+    #[arg(long, default_value_t = MAX_REQUEST_BODY_SIZE)]
+    max_body_size: u64,
+
+    /// Maximum size in bytes of a request body that is held in memory, like multipart forms and
+    /// compressed bodies. Uploads written to disk are only limited by --max-body-size
+    #[arg(long, default_value_t = MAX_BUFFERED_BODY_SIZE)]
+    max_buffered_body_size: u64,
+
+    /// File the log is written to, in addition to the console
+    #[arg(long)]
+    log_file: Option<PathBuf>,
+
+    /// Size in bytes after which the log file is rotated to `<log-file>.1`
+    #[arg(long, default_value_t = DEFAULT_LOG_FILE_MAX_SIZE)]
+    log_file_max_size: u64,
+
+    /// Origin allowed to make cross-origin requests, `*` allows any, can be repeated
+    #[arg(long = "cors-origin")]
+    cors_allowed_origins: Vec<String>,
+
+    /// Don't send the `X-Content-Type-Options`, `X-Frame-Options` and `Content-Security-Policy` headers
+    #[arg(long)]
+    no_security_headers: bool,
+
+    /// Value of the `Content-Security-Policy` header, an empty one isn't sent
+    #[arg(long, default_value = DEFAULT_CONTENT_SECURITY_POLICY)]
+    content_security_policy: String,
+
+    /// Value of the `Server` header
+    #[arg(long, default_value = DEFAULT_SERVER_HEADER)]
+    server_header: String,
+
+    /// Don't send the `Server` header at all
+    #[arg(long)]
+    no_server_header: bool,
+
+    /// Value of the `Content-Language` header, like `en-US`, not sent when omitted
+    #[arg(long)]
+    content_language: Option<String>,
+
+    /// Maximum number of requests per second from a single IP address, unlimited when omitted
+    #[arg(long)]
+    rate_limit: Option<u32>,
+
+    /// Maximum number of connections being handled or waiting for a worker,
+    /// further ones are answered with 503. Unlimited when omitted
+    #[arg(long)]
+    max_connections: Option<usize>,
+
+    /// MIME type of an extension, like `wasm=application/wasm`, can be repeated.
+    /// Takes precedence over the guessed types
+    #[arg(long = "mime-type", value_parser = parse_mime_type)]
+    mime_types: Vec<(String, String)>,
+}
+
+fn parse_mime_type(mime_type: &str) -> Result<(String, String), String> {
+    match mime_type.split_once('=') {
+        Some((extension, mime_type)) if !extension.is_empty() && !mime_type.is_empty() => Ok((
+            extension.trim_start_matches('.').to_ascii_lowercase(),
+            mime_type.to_string(),
+        )),
+        _ => Err(format!("Expected `extension=type`, got {:?}", mime_type)),
+    }
+}
+
+fn parse_virtual_host(virtual_host: &str) -> Result<(String, PathBuf), String> {
+    match virtual_host.split_once('=') {
+        Some((host, root)) if !host.is_empty() && !root.is_empty() => {
+            Ok((host.to_ascii_lowercase(), PathBuf::from(root)))
+        }
+        _ => Err(format!("Expected `host=directory`, got {:?}", virtual_host)),
+    }
+}
+
+fn socket_timeout(secs: u64) -> Option<Duration> {
+    (secs != 0).then(|| Duration::from_secs(secs))
+}
+
+fn load_authenticator(args: &Args) -> Authenticator {
+    if let Some(credentials_file) = &args.credentials_file {
+        Authenticator::from_file(credentials_file).expect("Failed to load credentials")
+    } else if env::var_os(CREDENTIALS_ENV_VAR).is_some() {
+        Authenticator::from_env(CREDENTIALS_ENV_VAR).expect("Failed to load credentials")
+    } else {
+        if !args.protected_paths.is_empty() {
+            warn!("No credentials were configured, protected paths can't be accessed");
+        }
+        Authenticator::default()
+    }
+}
+
+fn load_cache(args: &Args) -> Arc<dyn ResponseStore> {
+    if args.memory_cache {
+        Arc::new(MemoryCache::new(args.max_cache_size))
+    } else {
+        Arc::new(DiskCache::new(args.cache_dir.clone(), args.max_cache_size))
+    }
+}
+
+fn bind_listeners(ip: IpAddr, args: &Args) -> io::Result<Vec<TcpListener>> {
+    if !args.dual_stack {
+        return Ok(vec![TcpListener::bind(SocketAddr::new(ip, args.port))?]);
+    }
+
+    let ipv6_listener = TcpListener::bind(SocketAddr::new(
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        args.port,
+    ))?;
+    // Unless the system is configured otherwise, the IPv6 socket already accepts IPv4 connections
+    // as v4-mapped addresses and holds the port for both
+    match TcpListener::bind(SocketAddr::new(
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        args.port,
+    )) {
+        Ok(ipv4_listener) => Ok(vec![ipv6_listener, ipv4_listener]),
+        Err(err) if err.kind() == io::ErrorKind::AddrInUse => Ok(vec![ipv6_listener]),
+        Err(err) => Err(err),
+    }
+}
+
+// Connecting to the unspecified address isn't portable, the loopback one of the family is used
+fn wake_up_address(mut address: SocketAddr) -> SocketAddr {
+    if address.ip().is_unspecified() {
+        address.set_ip(match address {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        });
+    }
+    address
 }
 
 fn main() {
-    log::set_logger(&CONSOLE_LOGGER).expect("Failed to set up console logger");
+    let args: Args = Args::parse();
+
+    let logger = Box::leak(Box::new(load_logger(&args)));
+    log::set_logger(logger).expect("Failed to set up logger");
     log::set_max_level(LevelFilter::Info);
 
-    let args: Args = Args::parse();
-    let ip = Ipv4Addr::from_str(&args.ip).unwrap_or_else(|_| {
+    let ip = IpAddr::from_str(&args.ip).unwrap_or_else(|_| {
         let default_ip = default_ip();
         info!(
             "Invalid IP address provided, using default: {:?}",
@@ -66,17 +365,194 @@ fn main() {
         default_ip
     });
 
-    let socket = SocketAddr::new(IpAddr::V4(ip), args.port);
-    let listener = TcpListener::bind(socket).unwrap();
+    let shutdown = ShutdownSignal::new();
+    let config = Arc::new(ServerConfig {
+        authenticator: load_authenticator(&args),
+        cache: load_cache(&args),
+        protected_paths: args.protected_paths.clone(),
+        directory_listing: args.directory_listing,
+        index_file: args.index_file.clone(),
+        root: args.root.clone(),
+        fallback_roots: args.fallback_roots.clone(),
+        follow_symlinks: args.follow_symlinks,
+        virtual_hosts: args.virtual_hosts.iter().cloned().collect(),
+        strict_virtual_hosts: args.strict_virtual_hosts,
+        allowed_hidden_files: args.allowed_hidden_files.clone(),
+        request_timeout: Duration::from_secs(args.request_timeout),
+        read_timeout: socket_timeout(args.read_timeout),
+        write_timeout: socket_timeout(args.write_timeout),
+        max_header_size: args.max_header_size,
+        max_body_size: args.max_body_size,
+        max_buffered_body_size: args.max_buffered_body_size,
+        cors_allowed_origins: args.cors_allowed_origins.clone(),
+        security_headers: !args.no_security_headers,
+        content_security_policy: args.content_security_policy.clone(),
+        server_header: (!args.no_server_header).then(|| args.server_header.clone()),
+        content_language: args.content_language.clone(),
+        rate_limiter: args
+            .rate_limit
+            .map(|requests_per_second| Arc::new(RateLimiter::new(requests_per_second))),
+        max_age: args.max_age.map(Duration::from_secs),
+        mime_types: args.mime_types.iter().cloned().collect(),
+        router: Router::default(),
+        middleware: MiddlewareChain::default(),
+        shutdown: shutdown.clone(),
+    });
+
+    let listeners = bind_listeners(ip, &args).expect("Failed to bind the server address");
+
+    // Ctrl-C and SIGTERM stop accepting connections, open ones are closed after their current
+    // request
+    let listener_addresses: Vec<_> = listeners
+        .iter()
+        .filter_map(|listener| listener.local_addr().ok())
+        .collect();
+    ctrlc::set_handler(move || {
+        info!("Draining, no more connections are accepted");
+        shutdown.start_draining();
+        // Accept loops only see the signal once they accept a connection
+        for address in &listener_addresses {
+            let _ = TcpStream::connect(wake_up_address(*address));
+        }
+    })
+    .expect("Failed to set up the shutdown handler");
     let pool = ThreadPool::new(4);
+    let connection_limiter = Arc::new(args.max_connections.map(ConnectionLimiter::new));
 
-    info!("Server IP address: {:?}", socket);
+    // Every listener accepts connections on its own thread, they share the pool of workers
+    let accept_threads: Vec<_> = listeners
+        .into_iter()
+        .map(|listener| {
+            info!("Server IP address: {:?}", listener.local_addr().unwrap());
+            let config = Arc::clone(&config);
+            let pool = pool.clone();
+            let connection_limiter = Arc::clone(&connection_limiter);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if config.shutdown.is_draining() {
+                        break;
+                    }
+                    let mut stream = stream.unwrap();
+                    let client_address = match stream.peer_addr() {
+                        Ok(client_address) => client_address,
+                        Err(err) => {
+                            error!("Failed to get the client address: {:?}", err);
+                            continue;
+                        }
+                    };
+                    if let Err(err) = http_server::configure_stream(&stream, &config) {
+                        error!("Failed to configure the connection: {:?}", err);
+                        continue;
+                    }
+                    let connection_guard = match connection_limiter.as_ref() {
+                        Some(connection_limiter) => match connection_limiter.try_acquire() {
+                            Some(connection_guard) => Some(connection_guard),
+                            None => {
+                                warn!("Too many connections, rejecting {}", client_address);
+                                if let Err(err) = http_server::reject_connection(
+                                    &mut stream,
+                                    RETRY_AFTER,
+                                    &config,
+                                ) {
+                                    error!("{:?}", err)
+                                }
+                                continue;
+                            }
+                        },
+                        None => None,
+                    };
+                    let config = Arc::clone(&config);
+                    pool.execute(move || {
+                        // Held until the connection is finished
+                        let _connection_guard = connection_guard;
+                        if let Err(err) =
+                            http_server::handel_connection(&mut stream, client_address, &config)
+                        {
+                            error!("{:?}", err)
+                        }
+                    });
+                }
+            })
+        })
+        .collect();
 
-    for stream in listener.incoming() {
-        let mut stream = stream.unwrap();
-        pool.execute(move || match http_server::handel_connection(&mut stream) {
-            Err(err) => error!("{:?}", err),
-            _ => (),
-        });
+    for accept_thread in accept_threads {
+        accept_thread.join().expect("Accept loop panicked");
+    }
+    pool.join();
+    info!("Server stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use log::{Level, Log};
+
+    fn log_info(logger: &Logger, message: &str) {
+        logger.log(
+            &Record::builder()
+                .level(Level::Info)
+                .args(format_args!("{}", message))
+                .build(),
+        );
+    }
+
+    #[test]
+    fn logger_writes_to_file() {
+        log::set_max_level(LevelFilter::Info);
+        let log_file = env::temp_dir().join("http_server_test.log");
+        let _ = fs::remove_file(&log_file);
+
+        let logger = Logger {
+            file: Some(Mutex::new(
+                RotatingFile::open(log_file.clone(), 1024).unwrap(),
+            )),
+        };
+        log_info(&logger, "first line");
+        log_info(&logger, "second line");
+        logger.flush();
+
+        assert_eq!(
+            fs::read_to_string(&log_file).unwrap(),
+            "[INFO] first line\n[INFO] second line\n"
+        );
+    }
+
+    #[test]
+    fn logger_rotates_file() {
+        log::set_max_level(LevelFilter::Info);
+        let log_file = env::temp_dir().join("http_server_rotated.log");
+        let rotated_log_file = env::temp_dir().join("http_server_rotated.log.1");
+        let _ = fs::remove_file(&log_file);
+        let _ = fs::remove_file(&rotated_log_file);
+
+        // Every line is 14 bytes long, only two of them fit
+        let logger = Logger {
+            file: Some(Mutex::new(
+                RotatingFile::open(log_file.clone(), 30).unwrap(),
+            )),
+        };
+        for message in ["line 1", "line 2", "line 3"] {
+            log_info(&logger, message);
+        }
+
+        assert_eq!(
+            fs::read_to_string(&rotated_log_file).unwrap(),
+            "[INFO] line 1\n[INFO] line 2\n"
+        );
+        assert_eq!(fs::read_to_string(&log_file).unwrap(), "[INFO] line 3\n");
+    }
+
+    #[test]
+    fn wake_up_address_of_listener() {
+        for (address, expected) in [
+            ("0.0.0.0:4421", "127.0.0.1:4421"),
+            ("[::]:4421", "[::1]:4421"),
+            ("192.168.1.10:80", "192.168.1.10:80"),
+        ] {
+            let address = SocketAddr::from_str(address).unwrap();
+            assert_eq!(wake_up_address(address).to_string(), expected);
+        }
     }
 }
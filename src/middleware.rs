@@ -0,0 +1,130 @@
+use std::{fmt, sync::Arc};
+
+use crate::{request::HttpRequest, response::HttpResponse};
+
+// Rest of the chain, ending with the built-in behavior
+pub type Next<'a> = &'a mut dyn FnMut(&HttpRequest) -> HttpResponse;
+pub type Middleware = Arc<dyn Fn(&HttpRequest, Next) -> HttpResponse + Send + Sync>;
+
+// Wrappers around the handling of every request. Each one can answer on its own, without
+// calling `next`, or change the response of the rest of the chain
+#[derive(Clone, Default)]
+pub struct MiddlewareChain {
+    middlewares: Vec<Middleware>,
+}
+
+impl fmt::Debug for MiddlewareChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MiddlewareChain")
+            .field("len", &self.middlewares.len())
+            .finish()
+    }
+}
+
+impl MiddlewareChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The first middleware added is the outermost one
+    pub fn with(
+        mut self,
+        middleware: impl Fn(&HttpRequest, Next) -> HttpResponse + Send + Sync + 'static,
+    ) -> Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.middlewares.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.middlewares.len()
+    }
+
+    pub fn run(&self, request: &HttpRequest, handler: Next) -> HttpResponse {
+        self.run_from(0, request, handler)
+    }
+
+    fn run_from(&self, index: usize, request: &HttpRequest, handler: Next) -> HttpResponse {
+        match self.middlewares.get(index) {
+            Some(middleware) => middleware(request, &mut |request| {
+                self.run_from(index + 1, request, &mut *handler)
+            }),
+            None => handler(request),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use crate::{
+        common::{ErrorCode, ResponseCode, SuccessCode},
+        request::parse_http_request,
+        response::HttpResponseBuilder,
+    };
+
+    fn ok_handler(_: &HttpRequest) -> HttpResponse {
+        HttpResponseBuilder::default(ResponseCode::Success(SuccessCode::Ok)).build()
+    }
+
+    #[test]
+    fn middleware_order_and_short_circuit() {
+        let chain = MiddlewareChain::new()
+            .with(|request, next| {
+                let mut response = next(request);
+                let order = response.content().get_header("x-order").cloned();
+                response.add_header("x-order", format!("{}, outer", order.unwrap()));
+                response
+            })
+            .with(|request, next| {
+                if request.content().get_header("authorization").is_none() {
+                    return HttpResponseBuilder::default(ResponseCode::Error(
+                        ErrorCode::Unauthorized,
+                    ))
+                    .header("x-order", "rejected")
+                    .build();
+                }
+                let mut response = next(request);
+                response.add_header("x-order", "inner");
+                response
+            });
+        assert_eq!(chain.len(), 2);
+
+        let mut stream = Cursor::new(
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nAuthorization: Basic YTpi\r\n\r\n".to_vec(),
+        );
+        let request = parse_http_request(&mut stream).unwrap();
+        let response = chain.run(&request, &mut ok_handler);
+        assert_eq!(
+            response.status_code(),
+            ResponseCode::Success(SuccessCode::Ok)
+        );
+        assert_eq!(
+            response.content().get_header("x-order").unwrap(),
+            "inner, outer"
+        );
+
+        let mut stream = Cursor::new(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n".to_vec());
+        let request = parse_http_request(&mut stream).unwrap();
+        let mut handler_called = false;
+        let response = chain.run(&request, &mut |request| {
+            handler_called = true;
+            ok_handler(request)
+        });
+        assert!(!handler_called);
+        assert_eq!(
+            response.status_code(),
+            ResponseCode::Error(ErrorCode::Unauthorized)
+        );
+        assert_eq!(
+            response.content().get_header("x-order").unwrap(),
+            "rejected, outer"
+        );
+    }
+}
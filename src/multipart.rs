@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use log::error;
+
+use crate::common::{ErrorCode, HttpMessageContent, InternalHttpError};
+
+pub const MULTIPART_FORM_DATA: &str = "multipart/form-data";
+const MAX_PARTS: usize = 1_000;
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn malformed_body() -> anyhow::Error {
+    anyhow!(InternalHttpError::KnownError(ErrorCode::BadRequest))
+}
+
+// Parameters of a header value, like `form-data; name="file"; filename="a.txt"`
+fn header_parameter(header: &str, parameter_name: &str) -> Option<String> {
+    header.split(';').skip(1).find_map(|parameter| {
+        let (name, value) = parameter.split_once('=')?;
+        if name.trim().eq_ignore_ascii_case(parameter_name) {
+            Some(value.trim().trim_matches('"').to_string())
+        } else {
+            None
+        }
+    })
+}
+
+#[derive(Debug)]
+pub struct MultipartForm {
+    parts: Vec<HttpMessageContent>,
+}
+
+impl MultipartForm {
+    // Boundary of a `multipart/form-data; boundary=...` content type
+    pub fn boundary(content_type: &str) -> Option<String> {
+        let media_type = content_type.split(';').next()?.trim();
+        if !media_type.eq_ignore_ascii_case(MULTIPART_FORM_DATA) {
+            return None;
+        }
+        header_parameter(content_type, "boundary").filter(|boundary| !boundary.is_empty())
+    }
+
+    pub fn parse(body: &[u8], boundary: &str) -> Result<Self> {
+        let delimiter = format!("\r\n--{}", boundary).into_bytes();
+        // The first delimiter isn't necessarily preceded by a line break
+        let mut offset = if body.starts_with(&delimiter[2..]) {
+            delimiter.len() - 2
+        } else {
+            find(body, &delimiter).ok_or_else(malformed_body)? + delimiter.len()
+        };
+
+        let mut parts = Vec::new();
+        loop {
+            let rest = &body[offset..];
+            if rest.starts_with(b"--") {
+                return Ok(Self { parts });
+            }
+            if parts.len() == MAX_PARTS {
+                error!("Too many parts in multipart form");
+                return Err(anyhow!(InternalHttpError::KnownError(
+                    ErrorCode::ContentTooLarge
+                )));
+            }
+            let part_start = find(rest, b"\r\n").ok_or_else(malformed_body)? + 2;
+            let part_end = find(rest, &delimiter).ok_or_else(malformed_body)?;
+            if part_end < part_start {
+                return Err(malformed_body());
+            }
+
+            parts.push(MultipartForm::parse_part(&rest[part_start..part_end])?);
+            offset += part_end + delimiter.len();
+        }
+    }
+
+    fn parse_part(part: &[u8]) -> Result<HttpMessageContent> {
+        let (raw_headers, content) = if part.starts_with(b"\r\n") {
+            // No headers at all
+            (&part[..0], &part[2..])
+        } else {
+            let headers_end = find(part, b"\r\n\r\n").ok_or_else(malformed_body)?;
+            (&part[..headers_end], &part[headers_end + 4..])
+        };
+
+        let raw_headers = std::str::from_utf8(raw_headers)
+            .map_err(|_| anyhow!(InternalHttpError::InvalidUTF8Char))?;
+        let mut part = HttpMessageContent::new(HashMap::new(), content.to_vec());
+        for header in raw_headers
+            .split("\r\n")
+            .filter(|header| !header.is_empty())
+        {
+            let (name, value) = header.split_once(':').ok_or_else(malformed_body)?;
+            part.add_header(name.trim(), value.trim());
+        }
+        Ok(part)
+    }
+
+    pub fn parts(&self) -> &[HttpMessageContent] {
+        &self.parts
+    }
+
+    pub fn part_name(part: &HttpMessageContent) -> Option<String> {
+        header_parameter(part.get_header("content-disposition")?, "name")
+    }
+
+    pub fn part_filename(part: &HttpMessageContent) -> Option<String> {
+        header_parameter(part.get_header("content-disposition")?, "filename")
+    }
+
+    // First part that carries a file, rather than a plain form field
+    pub fn file_part(&self) -> Option<&HttpMessageContent> {
+        self.parts
+            .iter()
+            .find(|part| MultipartForm::part_filename(part).is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDARY: &str = "----WebKitFormBoundary7MA4YWxkTrZu0gW";
+
+    fn form_body() -> Vec<u8> {
+        format!(
+            "--{boundary}\r\n\
+             Content-Disposition: form-data; name=\"description\"\r\n\
+             \r\n\
+             holiday photos\r\n\
+             --{boundary}\r\n\
+             Content-Disposition: form-data; name=\"file\"; filename=\"notes.txt\"\r\n\
+             Content-Type: text/plain\r\n\
+             \r\n\
+             first line\r\n\
+             second line\r\n\
+             --{boundary}--\r\n",
+            boundary = BOUNDARY
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn multipart_boundary() {
+        let content_type = format!("multipart/form-data; boundary={}", BOUNDARY);
+        assert_eq!(MultipartForm::boundary(&content_type).unwrap(), BOUNDARY);
+        assert_eq!(
+            MultipartForm::boundary("Multipart/Form-Data; charset=utf-8; boundary=\"a b\"")
+                .unwrap(),
+            "a b"
+        );
+        assert!(MultipartForm::boundary("text/plain; boundary=abc").is_none());
+        assert!(MultipartForm::boundary("multipart/form-data").is_none());
+    }
+
+    #[test]
+    fn multipart_parse_form() {
+        let form = MultipartForm::parse(&form_body(), BOUNDARY).unwrap();
+        assert_eq!(form.parts().len(), 2);
+
+        let field = &form.parts()[0];
+        assert_eq!(MultipartForm::part_name(field).unwrap(), "description");
+        assert!(MultipartForm::part_filename(field).is_none());
+        assert_eq!(field.get_body(), b"holiday photos");
+
+        let file = form.file_part().unwrap();
+        assert_eq!(MultipartForm::part_name(file).unwrap(), "file");
+        assert_eq!(MultipartForm::part_filename(file).unwrap(), "notes.txt");
+        assert_eq!(file.get_header("content-type").unwrap(), "text/plain");
+        assert_eq!(file.get_body(), b"first line\r\nsecond line");
+    }
+
+    #[test]
+    fn multipart_parse_malformed() {
+        let body = form_body();
+        // Missing closing delimiter
+        assert!(MultipartForm::parse(&body[..body.len() - 20], BOUNDARY).is_err());
+        assert!(MultipartForm::parse(&body, "other-boundary").is_err());
+        assert!(MultipartForm::parse(b"", BOUNDARY).is_err());
+    }
+
+    #[test]
+    fn multipart_parse_preamble() {
+        let body = [b"preamble\r\n".as_slice(), &form_body()].concat();
+        let form = MultipartForm::parse(&body, BOUNDARY).unwrap();
+        assert_eq!(form.parts().len(), 2);
+        assert_eq!(form.parts()[0].get_body(), b"holiday photos");
+    }
+
+    #[test]
+    fn multipart_parse_too_many_parts() {
+        let part = format!("--{}\r\n\r\nvalue\r\n", BOUNDARY);
+        let form_with_parts =
+            |amount| format!("{}--{}--\r\n", part.repeat(amount), BOUNDARY).into_bytes();
+
+        let form = MultipartForm::parse(&form_with_parts(MAX_PARTS), BOUNDARY).unwrap();
+        assert_eq!(form.parts().len(), MAX_PARTS);
+        assert!(form.parts().iter().all(|part| part.get_body() == b"value"));
+
+        let err = MultipartForm::parse(&form_with_parts(MAX_PARTS + 1), BOUNDARY).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<InternalHttpError>(),
+            Some(InternalHttpError::KnownError(ErrorCode::ContentTooLarge))
+        ));
+    }
+}
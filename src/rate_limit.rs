@@ -0,0 +1,95 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// Buckets of clients that are back to full capacity are dropped once there are this many
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// Token bucket per client IP. Every client can burst up to `requests_per_second` requests,
+// tokens are refilled at the same rate
+#[derive(Debug)]
+pub struct RateLimiter {
+    requests_per_second: u32,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(requests_per_second: u32) -> Self {
+        Self {
+            requests_per_second: requests_per_second.max(1),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Takes a token for the request, or returns the time until the next one is available
+    pub fn check(&self, client: IpAddr) -> Result<(), Duration> {
+        self.check_at(client, Instant::now())
+    }
+
+    fn check_at(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let capacity = f64::from(self.requests_per_second);
+        let mut buckets = self
+            .buckets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * capacity
+                    < capacity
+            });
+        }
+
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let refilled = now.duration_since(bucket.updated).as_secs_f64() * capacity;
+        bucket.tokens = (bucket.tokens + refilled).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / capacity))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn rate_limiter_threshold() {
+        let limiter = RateLimiter::new(2);
+        let client = IpAddr::V4(Ipv4Addr::new(192, 168, 0, 7));
+        let other_client = IpAddr::V4(Ipv4Addr::new(192, 168, 0, 8));
+        let start = Instant::now();
+
+        assert!(limiter.check_at(client, start).is_ok());
+        assert!(limiter.check_at(client, start).is_ok());
+        let retry_after = limiter.check_at(client, start).unwrap_err();
+        assert_eq!(retry_after, Duration::from_millis(500));
+
+        // Other clients have their own budget
+        assert!(limiter.check_at(other_client, start).is_ok());
+
+        // Half a second later, one token is back
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.check_at(client, later).is_ok());
+        assert!(limiter.check_at(client, later).is_err());
+    }
+}
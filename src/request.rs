@@ -1,18 +1,24 @@
 use std::{
+    cmp,
     collections::HashMap,
-    io::{BufRead, BufReader, Read},
+    io::{self, BufRead, BufReader, Read, Write},
     str::FromStr,
-    sync::mpsc,
-    thread,
+    time::{Duration, Instant},
 };
 
 use crate::{
-    auth::AuthMethod, cache::CacheControl, common::*, compressor::ContentEncoding, url::Url,
+    auth::AuthMethod,
+    cache::CacheControl,
+    common::*,
+    compressor::{Compressor, ContentEncoding},
+    multipart::MultipartForm,
+    url::Url,
 };
 
 use anyhow::{anyhow, Context, Error, Result};
 use log::{info, trace};
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum HttpRequestMethod {
     OPTIONS,
@@ -44,7 +50,7 @@ impl FromStr for HttpRequestMethod {
 
 impl HttpRequestMethod {
     pub fn supported_methods() -> Vec<String> {
-        ["GET", "HEAD", "POST", "OPTIONS"]
+        ["GET", "HEAD", "POST", "PUT", "DELETE", "OPTIONS", "TRACE"]
             .into_iter()
             .map(|val| val.to_string())
             .collect()
@@ -76,6 +82,8 @@ pub struct HttpRequest {
     ranges: Option<Ranges>,
     cache_control: Option<CacheControl>,
     auth_info: Option<(AuthMethod, String)>,
+    // Length of the body that was left in the stream, see `HttpRequestReader::read_request_head`
+    streamed_body_length: u64,
 }
 
 impl HttpRequest {
@@ -91,6 +99,21 @@ impl HttpRequest {
         self.request_line.version.clone()
     }
 
+    // Body that was streamed, once it's read in full
+    pub(crate) fn set_body(&mut self, body: Vec<u8>) {
+        self.content.set_body(body);
+        self.streamed_body_length = 0;
+    }
+
+    // Host name and port the request is addressed to. The authority of an absolute-form target
+    // takes precedence over the `Host` header
+    pub fn host(&self) -> Option<(String, Option<u16>)> {
+        match self.request_line.url.authority() {
+            Some(authority) => parse_authority(&authority),
+            None => parse_authority(self.content.get_header("host")?),
+        }
+    }
+
     pub fn get_encoding(&self) -> Option<ContentEncoding> {
         self.requested_encoding
     }
@@ -110,6 +133,110 @@ impl HttpRequest {
     pub fn auth_info(&self) -> &Option<(AuthMethod, String)> {
         &self.auth_info
     }
+
+    fn has_connection_option(&self, option: &str) -> bool {
+        self.content
+            .get_header("connection")
+            .is_some_and(|connection| {
+                connection
+                    .split(',')
+                    .any(|value| value.trim().eq_ignore_ascii_case(option))
+            })
+    }
+
+    // HTTP/1.1 connections are persistent unless the client asks to close them,
+    // HTTP/1.0 connections are closed unless the client asks to keep them alive
+    pub fn keep_alive(&self) -> bool {
+        if self.request_line.version == "1.0" {
+            self.has_connection_option("keep-alive")
+        } else {
+            !self.has_connection_option("close")
+        }
+    }
+
+    // Conditional requests are answered based on validators of the current resource
+    pub fn is_conditional(&self) -> bool {
+        self.content.get_header("if-none-match").is_some()
+            || self.content.get_header("if-modified-since").is_some()
+    }
+
+    // The most specific media range that matches decides, `text/plain` over `text/*` over `*/*`.
+    // Everything is acceptable without the `Accept` header
+    pub fn accepts(&self, content_type: &str) -> bool {
+        let Some(accept) = self.content.get_header("accept") else {
+            return true;
+        };
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let (main_type, _) = essence.split_once('/').unwrap_or((&essence, ""));
+
+        parse_accept(accept)
+            .into_iter()
+            .filter_map(|(media_range, priority)| {
+                let specificity = if media_range == essence {
+                    3
+                } else if media_range.strip_suffix("/*") == Some(main_type) {
+                    2
+                } else if media_range == "*/*" {
+                    1
+                } else {
+                    return None;
+                };
+                Some((specificity, priority))
+            })
+            .max_by_key(|(specificity, _)| *specificity)
+            .is_some_and(|(_, priority)| priority > 0.0)
+    }
+
+    // The offered media type the client names with the highest priority, the first one on a tie.
+    // Wildcards don't count, they would pick a format the client never asked for
+    pub fn preferred_media_type<'offered>(
+        &self,
+        offered: &[&'offered str],
+    ) -> Option<&'offered str> {
+        let accept = parse_accept(self.content.get_header("accept")?);
+        offered
+            .iter()
+            .filter_map(|media_type| {
+                accept
+                    .iter()
+                    .find(|(media_range, _)| media_range == media_type)
+                    .map(|(_, priority)| (*media_type, *priority))
+            })
+            .filter(|(_, priority)| *priority > 0.0)
+            .fold(None, |preferred, (media_type, priority)| match preferred {
+                Some((_, preferred_priority)) if preferred_priority >= priority => preferred,
+                _ => Some((media_type, priority)),
+            })
+            .map(|(media_type, _)| media_type)
+    }
+
+    // Cookies from `Cookie: name=value; name2="value 2"`, segments without a name are skipped
+    pub fn cookies(&self) -> HashMap<String, String> {
+        let Some(cookie_header) = self.content.get_header("cookie") else {
+            return HashMap::new();
+        };
+        cookie_header
+            .split(';')
+            .filter_map(|cookie| {
+                let (name, value) = cookie.split_once('=')?;
+                let name = name.trim();
+                if name.is_empty() {
+                    return None;
+                }
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect()
+    }
 }
 
 pub struct HttpRequestBuilder(HttpRequest);
@@ -122,16 +249,12 @@ impl HttpRequestBuilder {
             ranges: None,
             cache_control: None,
             auth_info: None,
+            streamed_body_length: 0,
         })
     }
 
     pub fn set_range(mut self, ranges: Ranges) -> Self {
-        let range_content = if ranges.is_multipart() {
-            ranges.to_string()
-        } else {
-            let range = ranges.first().expect("Expected non-empty range");
-            format!("{}-{}", range.from, range.to)
-        };
+        let range_content = ranges.to_string();
         self.0.ranges = Some(ranges);
         self.header("Range", format!("bytes={}", range_content))
     }
@@ -141,6 +264,11 @@ impl HttpRequestBuilder {
         self
     }
 
+    pub fn set_encoding(mut self, encoding: ContentEncoding) -> Self {
+        self.0.requested_encoding = Some(encoding);
+        self.header("Accept-Encoding", encoding.to_string())
+    }
+
     pub fn header(
         mut self,
         header_name: impl Into<String>,
@@ -163,17 +291,47 @@ impl HttpRequestBuilder {
     }
 }
 
+// `example.com`, `example.com:8080` or `[::1]:443`. Host names are case insensitive, they're
+// lowercased. Brackets around IPv6 literals are removed
+fn parse_authority(authority: &str) -> Option<(String, Option<u16>)> {
+    let authority = authority.trim();
+    let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+        let (host, rest) = rest.split_once(']')?;
+        let port = match rest {
+            "" => None,
+            _ => Some(rest.strip_prefix(':')?),
+        };
+        (host, port)
+    } else {
+        match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        }
+    };
+    if host.is_empty() {
+        return None;
+    }
+    // An empty port is allowed, the default one of the scheme is meant
+    let port = match port.filter(|port| !port.is_empty()) {
+        Some(port) => Some(port.parse::<u16>().ok()?),
+        None => None,
+    };
+    Some((host.to_ascii_lowercase(), port))
+}
+
 fn get_http_version(version_line: &str) -> Result<String> {
-    let version = ["1.1"]
+    let version = ["1.0", "1.1"]
         .iter()
         .find(|&&version| version_line.ends_with(version))
-        .ok_or_else(|| InternalHttpError::KnownError(ErrorCode::HTTPVersionNotSupported))?;
+        .ok_or(InternalHttpError::KnownError(
+            ErrorCode::HTTPVersionNotSupported,
+        ))?;
 
     Ok(version.to_string())
 }
 
-fn parse_header(header: &String) -> Result<(String, String)> {
-    if header.len() as u64 > MAX_HEADER_SIZE {
+fn parse_header(header: &str, max_header_size: u64) -> Result<(String, String)> {
+    if header.len() as u64 > max_header_size {
         return Err(anyhow!(InternalHttpError::KnownError(
             ErrorCode::RequestHeaderFieldsTooLarge
         )));
@@ -185,6 +343,10 @@ fn parse_header(header: &String) -> Result<(String, String)> {
     if header_parsed.0.is_empty() || header_parsed.1.is_empty() {
         return Err(anyhow!(InternalHttpError::WrongHeaderFormat));
     }
+    // `Name : value` could be read as another header by proxies on the way, RFC 7230 section 3.2.4
+    if header_parsed.0.ends_with([' ', '\t']) {
+        return Err(anyhow!(InternalHttpError::WrongHeaderFormat));
+    }
 
     trace!("Parsed header: {} {}", header_parsed.0, header_parsed.1);
     Ok((
@@ -193,6 +355,42 @@ fn parse_header(header: &String) -> Result<(String, String)> {
     ))
 }
 
+// Repeated header lines are combined into one comma separated value, RFC 7230 section 3.2.2.
+// Message framing and the target host can't be ambiguous, conflicting values are rejected
+fn add_header_line(
+    headers: &mut HashMap<String, String>,
+    name: String,
+    value: String,
+) -> Result<()> {
+    match headers.get_mut(&name) {
+        Some(existing) if name == "content-length" => {
+            if *existing != value {
+                return Err(anyhow!(InternalHttpError::KnownError(
+                    ErrorCode::BadRequest
+                )));
+            }
+        }
+        Some(_) if name == "host" => {
+            return Err(anyhow!(InternalHttpError::KnownError(
+                ErrorCode::BadRequest
+            )));
+        }
+        // Cookies are separated by semicolons rather than commas
+        Some(existing) if name == "cookie" => {
+            existing.push_str("; ");
+            existing.push_str(&value);
+        }
+        Some(existing) => {
+            existing.push_str(", ");
+            existing.push_str(&value);
+        }
+        None => {
+            headers.insert(name, value);
+        }
+    }
+    Ok(())
+}
+
 // Parse string: "br;q=1.0, gzip;q=0.8, *;q=0.1"
 fn parse_encodings(accepted_encodings: &str) -> Result<Vec<ContentEncoding>> {
     let mut encodings_by_priority: Vec<(ContentEncoding, f32)> = Vec::new();
@@ -208,10 +406,14 @@ fn parse_encodings(accepted_encodings: &str) -> Result<Vec<ContentEncoding>> {
         let content_encoding = ContentEncoding::from_str(name.trim())
             .context(format!("Unknown content encoding {:?}", name))?;
 
-        encodings_by_priority.push((content_encoding, priority));
+        // q=0 means "not acceptable"
+        if priority > 0.0 {
+            encodings_by_priority.push((content_encoding, priority));
+        }
     }
 
-    encodings_by_priority.sort_by(|lhs, rhs| lhs.1.partial_cmp(&rhs.1).unwrap());
+    // Highest priority first, keep the client's order for equal priorities
+    encodings_by_priority.sort_by(|lhs, rhs| rhs.1.partial_cmp(&lhs.1).unwrap());
     let res = encodings_by_priority
         .into_iter()
         .map(|(content_encoding, _)| content_encoding)
@@ -219,16 +421,69 @@ fn parse_encodings(accepted_encodings: &str) -> Result<Vec<ContentEncoding>> {
     Ok(res)
 }
 
-fn choose_content_encoding(content_encodings: &Vec<ContentEncoding>) -> Result<ContentEncoding> {
+// Parse string: "text/html, text/*;q=0.8, */*;q=0.1". Media ranges with a malformed
+// q-value are skipped
+fn parse_accept(accept: &str) -> Vec<(String, f32)> {
+    accept
+        .split(',')
+        .filter_map(|media_range| {
+            let mut parameters = media_range.split(';');
+            let media_type = parameters.next()?.trim().to_ascii_lowercase();
+            if media_type.is_empty() {
+                return None;
+            }
+            let priority = match parameters
+                .filter_map(|parameter| parameter.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            {
+                Some((_, priority)) => priority.trim().parse::<f32>().ok()?,
+                None => 1.0,
+            };
+            Some((media_type, priority))
+        })
+        .collect()
+}
+
+fn choose_content_encoding(content_encodings: &[ContentEncoding]) -> Result<ContentEncoding> {
     let Some(supported_encoding) = content_encodings
-        .into_iter()
+        .iter()
         .find(|encoding| encoding.is_supported())
     else {
         return Err(anyhow!(InternalHttpError::KnownError(
             ErrorCode::NotAcceptable
         )));
     };
-    Ok(supported_encoding.clone())
+    Ok(*supported_encoding)
+}
+
+// Undo the content-encoding applied by the client, so handlers always see the original body
+// A small body can inflate to much more, the decoded one is held to the limit as well
+fn decode_body(
+    headers: &mut HashMap<String, String>,
+    body: Vec<u8>,
+    max_body_size: u64,
+) -> Result<Vec<u8>> {
+    let Some(content_encoding) = headers.get("content-encoding") else {
+        return Ok(body);
+    };
+
+    let content_encoding = ContentEncoding::from_str(content_encoding)
+        .ok()
+        .filter(|content_encoding| content_encoding.is_supported())
+        .ok_or(InternalHttpError::KnownError(
+            ErrorCode::UnsupportedMediaType,
+        ))?;
+    let body = Compressor::decompress_limited(&body, content_encoding, max_body_size)
+        .map_err(|_| InternalHttpError::KnownError(ErrorCode::BadRequest))?;
+    if body.len() as u64 > max_body_size {
+        return Err(anyhow!(InternalHttpError::KnownError(
+            ErrorCode::ContentTooLarge
+        )));
+    }
+
+    headers.remove("content-encoding");
+    headers.insert("content-length".to_string(), body.len().to_string());
+    Ok(body)
 }
 
 fn get_auth_information(authorization_string: &str) -> Result<(AuthMethod, String)> {
@@ -240,9 +495,51 @@ fn get_auth_information(authorization_string: &str) -> Result<(AuthMethod, Strin
     Ok((auth_method, auth_data.to_string()))
 }
 
-pub fn parse_http_request_internal(stream: &mut impl HttpStream) -> Result<HttpRequest> {
-    let mut buf_reader = BufReader::new(stream);
+// Interim response for clients that wait before sending the body
+const CONTINUE_RESPONSE: &[u8] = b"HTTP/1.1 100 Continue\r\n\r\n";
 
+fn expects_continue(headers: &HashMap<String, String>, version: &str) -> bool {
+    version == "1.1"
+        && headers
+            .get("expect")
+            .is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue"))
+}
+
+// Uploads that are written to disk as they are, everything else needs the whole body to be handled
+fn is_plain_upload(method: HttpRequestMethod, headers: &HashMap<String, String>) -> bool {
+    matches!(method, HttpRequestMethod::POST | HttpRequestMethod::PUT)
+        && !headers.contains_key("content-encoding")
+        && headers
+            .get("content-type")
+            .is_none_or(|content_type| MultipartForm::boundary(content_type).is_none())
+}
+
+// Exceeding them is answered with 431 and 413 respectively
+#[derive(Debug, Clone, Copy)]
+pub struct RequestLimits {
+    // Of a single header line
+    pub max_header_size: u64,
+    pub max_body_size: u64,
+    // Of bodies that are read into memory: multipart forms, encoded bodies, those of routes and
+    // every body when uploads aren't streamed. Decoded bodies are held to it as well
+    pub max_buffered_body_size: u64,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_header_size: MAX_HEADER_SIZE,
+            max_body_size: MAX_REQUEST_BODY_SIZE,
+            max_buffered_body_size: MAX_BUFFERED_BODY_SIZE,
+        }
+    }
+}
+
+pub fn parse_http_request_internal<S: Read + Write>(
+    buf_reader: &mut BufReader<S>,
+    stream_uploads: bool,
+    limits: RequestLimits,
+) -> Result<HttpRequest> {
     // Parse request line
     let mut request_line = String::new();
     buf_reader
@@ -270,9 +567,20 @@ pub fn parse_http_request_internal(stream: &mut impl HttpStream) -> Result<HttpR
         .map_err(|_| anyhow!(InternalHttpError::KnownError(ErrorCode::NotImplemented)))?;
     let version = get_http_version(version)?;
     let url = Url::new(resource);
+    // Asterisk-form is only meaningful for OPTIONS. Control characters, a NUL in particular,
+    // could end the path early once it's handed to the file system
+    let is_valid_resource = url.decoded_resource().is_some_and(|decoded| {
+        !decoded.chars().any(|c| c.is_control()) && !resource.chars().any(|c| c.is_ascii_control())
+    });
+    if !is_valid_resource || (resource == "*" && method != HttpRequestMethod::OPTIONS) {
+        return Err(anyhow!(InternalHttpError::KnownError(
+            ErrorCode::BadRequest
+        )));
+    }
 
     // Parse headers
     let mut headers: HashMap<String, String> = HashMap::new();
+    let mut header_lines = 0;
     loop {
         let mut line = String::new();
         buf_reader
@@ -284,14 +592,57 @@ pub fn parse_http_request_internal(stream: &mut impl HttpStream) -> Result<HttpR
             break;
         }
 
-        let header = parse_header(&line)?;
-        headers.insert(header.0, header.1);
+        let (name, value) = parse_header(&line, limits.max_header_size)?;
+        add_header_line(&mut headers, name, value)?;
 
-        if headers.len() > MAX_HEADERS_AMOUNT {
+        header_lines += 1;
+        if header_lines > MAX_HEADERS_AMOUNT {
             return Err(anyhow!(InternalHttpError::HeaderOverflow));
         }
     }
 
+    // HTTP/1.0 clients may leave it out, HTTP/1.1 ones must always name the host they're addressing
+    if version == "1.1" && !headers.contains_key("host") {
+        return Err(anyhow!(InternalHttpError::KnownError(
+            ErrorCode::BadRequest
+        )));
+    }
+
+    // `100-continue` is the only expectation there is, others can't be met
+    if headers
+        .get("expect")
+        .is_some_and(|expect| !expect.trim().eq_ignore_ascii_case("100-continue"))
+    {
+        return Err(anyhow!(InternalHttpError::KnownError(
+            ErrorCode::ExpectationFailed
+        )));
+    }
+
+    // Proxies on the way could frame the body by the other header, which allows smuggling a
+    // request within the body
+    if headers.contains_key("content-length") && headers.contains_key("transfer-encoding") {
+        return Err(anyhow!(InternalHttpError::KnownError(
+            ErrorCode::BadRequest
+        )));
+    }
+
+    // Chunked bodies aren't decoded. Taking such a body as empty would leave the chunks in the
+    // stream, to be parsed as the next request
+    if headers.contains_key("transfer-encoding") {
+        return Err(anyhow!(InternalHttpError::KnownError(
+            ErrorCode::NotImplemented
+        )));
+    }
+
+    // Without the header the body would silently be taken as empty
+    if matches!(method, HttpRequestMethod::POST | HttpRequestMethod::PUT)
+        && !headers.contains_key("content-length")
+    {
+        return Err(anyhow!(InternalHttpError::KnownError(
+            ErrorCode::LengthRequired
+        )));
+    }
+
     let content_length = if let Some(content_length) = headers.get("content-length") {
         content_length
             .parse::<u64>()
@@ -300,23 +651,44 @@ pub fn parse_http_request_internal(stream: &mut impl HttpStream) -> Result<HttpR
         0
     };
 
-    // Allow max body length up to 2 GB
-    if content_length > MAX_REQUEST_BODY_SIZE {
+    let streamed_body_length = if stream_uploads && is_plain_upload(method, &headers) {
+        content_length
+    } else {
+        0
+    };
+
+    if content_length > limits.max_body_size
+        || (streamed_body_length == 0 && content_length > limits.max_buffered_body_size)
+    {
         return Err(anyhow!(InternalHttpError::KnownError(
             ErrorCode::ContentTooLarge
         )));
     }
 
+    // Requests that are going to be rejected get the final response instead
+    if content_length != 0 && expects_continue(&headers, &version) {
+        let stream = buf_reader.get_mut();
+        stream
+            .write_all(CONTINUE_RESPONSE)
+            .and_then(|_| stream.flush())
+            .context("Failed to send 100 Continue")?;
+    }
+
     let mut body = Vec::new();
-    if content_length != 0 {
+    if content_length != 0 && streamed_body_length == 0 {
         body.resize(content_length as usize, 0);
         buf_reader
             .read_exact(&mut body)
             .context("Failed to read body of Http request")?;
     }
+    let body = decode_body(
+        &mut headers,
+        body,
+        cmp::min(limits.max_body_size, limits.max_buffered_body_size),
+    )?;
 
     let requested_encoding = if let Some(encodings) = headers.get("accept-encoding") {
-        let proposed_encodings = parse_encodings(&encodings)?;
+        let proposed_encodings = parse_encodings(encodings)?;
         let encoding = choose_content_encoding(&proposed_encodings)?;
         Some(encoding)
     } else {
@@ -324,7 +696,21 @@ pub fn parse_http_request_internal(stream: &mut impl HttpStream) -> Result<HttpR
         None
     };
 
-    let ranges = headers.get("range").and_then(|ranges| ranges.parse().ok());
+    // Ranges in other units are ignored and the whole resource is sent, malformed byte ranges
+    // are rejected
+    let ranges = match headers.get("range") {
+        Some(ranges)
+            if ranges
+                .split_once('=')
+                .is_some_and(|(unit, _)| Ranges::is_bytes_unit(unit)) =>
+        {
+            let ranges = ranges
+                .parse()
+                .map_err(|_| InternalHttpError::KnownError(ErrorCode::BadRequest))?;
+            Some(ranges)
+        }
+        _ => None,
+    };
     let cache_control = headers
         .get("cache-control")
         .and_then(|cache_control| cache_control.parse().ok());
@@ -339,30 +725,238 @@ pub fn parse_http_request_internal(stream: &mut impl HttpStream) -> Result<HttpR
         ranges,
         cache_control,
         auth_info,
+        streamed_body_length,
     })
 }
 
-pub fn parse_http_request(stream: &mut impl HttpStream) -> Result<HttpRequest> {
-    let (tx, rx) = mpsc::channel();
-    let mut stream_for_parser = stream.clone_stream();
-    // TODO: this is not a correct implementation as the spawn thread will continue to run even after
-    //       the timeout
-    thread::spawn(move || {
-        _ = tx.send(parse_http_request_internal(&mut stream_for_parser));
-    });
+// Reader that gives up once the deadline has passed. The read timeout of the stream is shrunk
+// to the time left before every read, so a client trickling bytes can't stall the parser forever
+struct DeadlineReader<'stream, S: HttpStream> {
+    stream: &'stream mut S,
+    deadline: Instant,
+    // Longest wait for a single read, regardless of the deadline
+    read_timeout: Option<Duration>,
+}
 
-    let Ok(parsed_http_request) = rx.recv_timeout(REQUEST_TIMEOUT) else {
-        return Err(anyhow!(InternalHttpError::KnownError(
-            ErrorCode::RequestTimeout
-        )));
-    };
-    parsed_http_request
+impl<'stream, S: HttpStream> DeadlineReader<'stream, S> {
+    fn new(stream: &'stream mut S, timeout: Duration) -> Self {
+        Self {
+            stream,
+            deadline: Instant::now() + timeout,
+            read_timeout: None,
+        }
+    }
+
+    fn reset_deadline(&mut self, timeout: Duration) {
+        self.deadline = Instant::now() + timeout;
+    }
+}
+
+impl<S: HttpStream> Read for DeadlineReader<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let time_left = self.deadline.saturating_duration_since(Instant::now());
+        if time_left.is_zero() {
+            return Err(io::Error::from(io::ErrorKind::TimedOut));
+        }
+        let timeout = self
+            .read_timeout
+            .map_or(time_left, |read_timeout| read_timeout.min(time_left));
+        self.stream.set_read_timeout(Some(timeout))?;
+        self.stream.read(buf)
+    }
+}
+
+// Interim responses are written while the request is being parsed
+impl<S: HttpStream> Write for DeadlineReader<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+fn is_timeout(error: &Error) -> bool {
+    error.chain().any(|cause| {
+        cause.downcast_ref::<io::Error>().is_some_and(|io_error| {
+            matches!(
+                io_error.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            )
+        })
+    })
+}
+
+// Reads consecutive requests from a persistent connection. The buffer is kept between requests,
+// so pipelined requests that were already read from the stream aren't lost
+pub struct HttpRequestReader<'stream, S: HttpStream> {
+    buf_reader: BufReader<DeadlineReader<'stream, S>>,
+    // Bytes of the last request body that weren't read from the stream yet
+    pending_body_length: u64,
+    limits: RequestLimits,
+}
+
+impl<'stream, S: HttpStream> HttpRequestReader<'stream, S> {
+    pub fn new(stream: &'stream mut S) -> Self {
+        Self {
+            buf_reader: BufReader::new(DeadlineReader::new(stream, REQUEST_TIMEOUT)),
+            pending_body_length: 0,
+            limits: RequestLimits::default(),
+        }
+    }
+
+    pub fn with_limits(mut self, limits: RequestLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    // Read timeout of the stream, it's restored once a request was read
+    pub fn with_read_timeout(mut self, read_timeout: Option<Duration>) -> Self {
+        self.buf_reader.get_mut().read_timeout = read_timeout;
+        self
+    }
+
+    pub fn read_request(&mut self, timeout: Duration) -> Result<HttpRequest> {
+        self.read_request_internal(timeout, false)
+    }
+
+    // Like `read_request`, but bodies of plain POST and PUT uploads are left in the stream,
+    // so they don't have to be kept in memory. They are read with `body`
+    pub fn read_request_head(&mut self, timeout: Duration) -> Result<HttpRequest> {
+        self.read_request_internal(timeout, true)
+    }
+
+    // Body of the last request that was left in the stream, it has to be read before the
+    // deadline of the request
+    pub fn body(&mut self) -> StreamedBody<'_, 'stream, S> {
+        StreamedBody {
+            buf_reader: &mut self.buf_reader,
+            length_left: &mut self.pending_body_length,
+        }
+    }
+
+    // The next request can't be read before the body of the last one
+    pub fn has_pending_body(&self) -> bool {
+        self.pending_body_length != 0
+    }
+
+    fn read_request_internal(
+        &mut self,
+        timeout: Duration,
+        stream_uploads: bool,
+    ) -> Result<HttpRequest> {
+        self.buf_reader.get_mut().reset_deadline(timeout);
+        let parsed_http_request =
+            parse_http_request_internal(&mut self.buf_reader, stream_uploads, self.limits);
+        self.pending_body_length = parsed_http_request
+            .as_ref()
+            .map_or(0, |request| request.streamed_body_length);
+        self.reset_read_timeout()?;
+
+        parsed_http_request.map_err(|error| {
+            if is_timeout(&error) {
+                anyhow!(InternalHttpError::KnownError(ErrorCode::RequestTimeout))
+            } else {
+                error
+            }
+        })
+    }
+
+    // Returns false when the client closed the connection or didn't start a new request in time
+    pub fn wait_for_request(&mut self, timeout: Duration) -> bool {
+        self.buf_reader.get_mut().reset_deadline(timeout);
+        let has_data = matches!(self.buf_reader.fill_buf(), Ok(data) if !data.is_empty());
+        has_data && self.reset_read_timeout().is_ok()
+    }
+
+    pub fn stream(&mut self) -> &mut S {
+        self.buf_reader.get_mut().stream
+    }
+
+    // Hands the connection over to another protocol, after `101 Switching Protocols`
+    pub fn upgrade(&mut self, idle_timeout: Duration) -> UpgradedConnection<'_, 'stream, S> {
+        UpgradedConnection {
+            buf_reader: &mut self.buf_reader,
+            idle_timeout,
+        }
+    }
+
+    fn reset_read_timeout(&self) -> Result<()> {
+        let deadline_reader = self.buf_reader.get_ref();
+        deadline_reader
+            .stream
+            .set_read_timeout(deadline_reader.read_timeout)
+            .context("Failed to reset read timeout")
+    }
+}
+
+// Reads exactly the length of the body, a client that closes the connection earlier makes the
+// read fail instead of cutting the upload short
+pub struct StreamedBody<'reader, 'stream, S: HttpStream> {
+    buf_reader: &'reader mut BufReader<DeadlineReader<'stream, S>>,
+    length_left: &'reader mut u64,
+}
+
+impl<S: HttpStream> Read for StreamedBody<'_, '_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if *self.length_left == 0 {
+            return Ok(0);
+        }
+        let max_len = buf
+            .len()
+            .min(usize::try_from(*self.length_left).unwrap_or(usize::MAX));
+        let read = self.buf_reader.read(&mut buf[..max_len])?;
+        if read == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        *self.length_left -= read as u64;
+        Ok(read)
+    }
+}
+
+// Data the client sent right after the handshake may already be buffered, so reads still go
+// through the buffer. Every read has to finish within the idle timeout
+pub struct UpgradedConnection<'reader, 'stream, S: HttpStream> {
+    buf_reader: &'reader mut BufReader<DeadlineReader<'stream, S>>,
+    idle_timeout: Duration,
+}
+
+impl<S: HttpStream> Read for UpgradedConnection<'_, '_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.buf_reader.get_mut().reset_deadline(self.idle_timeout);
+        self.buf_reader.read(buf)
+    }
+}
+
+impl<S: HttpStream> Write for UpgradedConnection<'_, '_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf_reader.get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.buf_reader.get_mut().flush()
+    }
+}
+
+pub fn parse_http_request_with_timeout(
+    stream: &mut impl HttpStream,
+    timeout: Duration,
+) -> Result<HttpRequest> {
+    HttpRequestReader::new(stream).read_request(timeout)
+}
+
+pub fn parse_http_request(stream: &mut impl HttpStream) -> Result<HttpRequest> {
+    parse_http_request_with_timeout(stream, REQUEST_TIMEOUT)
 }
 
 #[cfg(test)]
 mod test {
     use rand::Rng;
-    use std::io::Cursor;
+    use std::{
+        io::{Cursor, Write},
+        net::{TcpListener, TcpStream},
+    };
 
     use super::*;
 
@@ -404,9 +998,21 @@ mod test {
         assert_eq!(parsed_request.content.get_body(), b"Hello");
     }
 
+    #[test]
+    fn request_parse_http_1_0() {
+        let parsed_request = parse_request("GET / HTTP/1.0\r\n\r\n").unwrap();
+        assert_eq!(parsed_request.get_version(), "1.0");
+        assert!(!parsed_request.keep_alive());
+
+        let parsed_request =
+            parse_request("GET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n").unwrap();
+        assert!(parsed_request.keep_alive());
+    }
+
     #[test]
     fn request_parse_accept_encoding() {
-        let request = "GET / HTTP/1.1\r\nAccept-Encoding : br;q=0.8, gzip, *\r\n\r\n";
+        let request =
+            "GET / HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: br;q=0.8, gzip, *\r\n\r\n";
         let result = parse_request(request);
         assert!(result.is_ok());
 
@@ -418,6 +1024,142 @@ mod test {
         );
     }
 
+    #[test]
+    fn request_repeated_headers_combined() {
+        let request = "GET / HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip\r\nAccept-Encoding: br;q=0.5\r\n\
+                       Cookie: a=1\r\nCookie: b=2\r\n\r\n";
+        let parsed_request = parse_request(request).unwrap();
+
+        assert_eq!(
+            parsed_request
+                .content()
+                .get_header("accept-encoding")
+                .unwrap(),
+            "gzip, br;q=0.5"
+        );
+        assert_eq!(parsed_request.get_encoding(), Some(ContentEncoding::Gzip));
+        assert_eq!(
+            parsed_request.content().get_header("cookie").unwrap(),
+            "a=1; b=2"
+        );
+    }
+
+    #[test]
+    fn request_repeated_framing_headers() {
+        let request = "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\nContent-Length: 2\r\n\r\nhi";
+        let parsed_request = parse_request(request).unwrap();
+        assert_eq!(parsed_request.content().get_body(), b"hi");
+
+        for request in [
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\nContent-Length: 3\r\n\r\nhi!",
+            "GET / HTTP/1.1\r\nHost: a.example\r\nHost: b.example\r\n\r\n",
+        ] {
+            assert_eq!(
+                get_error(parse_request(request)),
+                InternalHttpError::KnownError(ErrorCode::BadRequest)
+            );
+        }
+    }
+
+    #[test]
+    fn request_parse_accept_encoding_highest_priority_first() {
+        let request =
+            "GET / HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip;q=0.8, br;q=1.0\r\n\r\n";
+        let parsed_request = parse_request(request).unwrap();
+
+        assert_eq!(parsed_request.get_encoding(), Some(ContentEncoding::Br));
+    }
+
+    #[test]
+    fn request_parse_accept_encoding_zero_priority() {
+        let request = "GET / HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: gzip;q=0, *\r\n\r\n";
+        let parsed_request = parse_request(request).unwrap();
+
+        assert_eq!(
+            parsed_request.get_encoding(),
+            Some(ContentEncoding::Identity)
+        );
+    }
+
+    #[test]
+    fn request_parse_accept_encoding_zstd() {
+        let request =
+            "GET / HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: zstd, gzip;q=0.5\r\n\r\n";
+        let parsed_request = parse_request(request).unwrap();
+
+        assert_eq!(parsed_request.get_encoding(), Some(ContentEncoding::Zstd));
+    }
+
+    #[test]
+    fn request_parse_accept_encoding_deflate() {
+        let request = "GET / HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: deflate\r\n\r\n";
+        let parsed_request = parse_request(request).unwrap();
+
+        assert_eq!(
+            parsed_request.get_encoding(),
+            Some(ContentEncoding::Deflate)
+        );
+    }
+
+    #[test]
+    fn request_accepts() {
+        let accepts = |accept: &str, content_type: &str| {
+            parse_request(&format!(
+                "GET / HTTP/1.1\r\nHost: localhost\r\nAccept: {}\r\n\r\n",
+                accept
+            ))
+            .unwrap()
+            .accepts(content_type)
+        };
+
+        assert!(accepts("text/plain", "text/plain"));
+        assert!(accepts("text/plain", "Text/Plain; charset=utf-8"));
+        assert!(!accepts("text/plain", "text/x-rust"));
+        assert!(accepts("text/*", "text/x-rust"));
+        assert!(accepts("image/png, */*;q=0.1", "text/html"));
+        assert!(!accepts("image/png", "text/html"));
+        // The most specific media range wins
+        assert!(!accepts("text/*, text/html;q=0", "text/html"));
+        assert!(accepts("*/*;q=0, text/*;level=1;q=0.5", "text/css"));
+        assert!(!accepts("text/html;q=abc", "text/html"));
+
+        let request = parse_request("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        assert!(request.accepts("application/octet-stream"));
+    }
+
+    #[test]
+    fn request_parse_cookies() {
+        let cookies = |cookie_header: &str| {
+            parse_request(&format!(
+                "GET / HTTP/1.1\r\nHost: localhost\r\nCookie: {}\r\n\r\n",
+                cookie_header
+            ))
+            .unwrap()
+            .cookies()
+        };
+
+        assert_eq!(
+            cookies("session=abc123"),
+            HashMap::from([(String::from("session"), String::from("abc123"))])
+        );
+        assert_eq!(
+            cookies(" session = abc123 ;theme=\"dark mode\"; empty="),
+            HashMap::from([
+                (String::from("session"), String::from("abc123")),
+                (String::from("theme"), String::from("dark mode")),
+                (String::from("empty"), String::new()),
+            ])
+        );
+        // Segments without `=` or a name are skipped, `=` in values is kept
+        assert_eq!(
+            cookies("broken; =nameless; token=a=b"),
+            HashMap::from([(String::from("token"), String::from("a=b"))])
+        );
+
+        let request = parse_request("GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        assert!(request.cookies().is_empty());
+    }
+
     // ERRORS
     #[test]
     fn request_malformed_request_line() {
@@ -443,8 +1185,8 @@ mod test {
     fn request_wrong_header_format() {
         let invalid_requests = vec![
             // invalid request line
-            String::from("GET / HTTP/1.1\r\nHeader:"),
-            String::from("GET / HTTP/1.1\r\n:"),
+            String::from("GET / HTTP/1.1\r\nHost: localhost\r\nHeader:"),
+            String::from("GET / HTTP/1.1\r\nHost: localhost\r\n:"),
         ];
 
         for request in invalid_requests {
@@ -454,12 +1196,382 @@ mod test {
         }
     }
 
+    #[test]
+    fn request_whitespace_before_colon() {
+        for header in ["Name : value", "Name\t: value", "Content-Length : 5"] {
+            let result = parse_request(&format!(
+                "GET / HTTP/1.1\r\nHost: localhost\r\n{}\r\n\r\n",
+                header
+            ));
+            assert_eq!(get_error(result), InternalHttpError::WrongHeaderFormat);
+        }
+
+        let parsed_request =
+            parse_request("GET / HTTP/1.1\r\nHost: localhost\r\nName: value\r\n\r\n").unwrap();
+        assert_eq!(
+            parsed_request.content().get_header("name").unwrap(),
+            "value"
+        );
+    }
+
+    #[test]
+    fn request_malformed_percent_encoding() {
+        for resource in ["/files/file%", "/files/file%2", "/files/%zz"] {
+            let result = parse_request(&format!(
+                "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                resource
+            ));
+            assert_eq!(
+                get_error(result),
+                InternalHttpError::KnownError(ErrorCode::BadRequest)
+            );
+        }
+    }
+
+    #[test]
+    fn request_content_length_with_transfer_encoding() {
+        let requests = [
+            "POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\nHello",
+            "POST /upload HTTP/1.1\r\nHost: localhost\r\ntransfer-encoding: gzip, chunked\r\ncontent-length: 0\r\n\r\n",
+        ];
+        for request in requests {
+            assert_eq!(
+                get_error(parse_request(request)),
+                InternalHttpError::KnownError(ErrorCode::BadRequest)
+            );
+        }
+    }
+
+    #[test]
+    fn request_transfer_encoding_not_implemented() {
+        for request in [
+            "POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: gzip\r\n\r\n",
+        ] {
+            assert_eq!(
+                get_error(parse_request(request)),
+                InternalHttpError::KnownError(ErrorCode::NotImplemented)
+            );
+        }
+    }
+
+    #[test]
+    fn request_asterisk_form() {
+        let request = parse_request("OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        assert_eq!(request.get_url().resource(), "*");
+
+        assert_eq!(
+            get_error(parse_request("GET * HTTP/1.1\r\nHost: localhost\r\n\r\n")),
+            InternalHttpError::KnownError(ErrorCode::BadRequest)
+        );
+    }
+
+    #[test]
+    fn request_configured_limits() {
+        let limits = RequestLimits {
+            max_header_size: 32,
+            max_body_size: 10,
+            ..Default::default()
+        };
+        let read_request = |request: &str| {
+            let mut stream = Cursor::new(request.as_bytes().to_vec());
+            HttpRequestReader::new(&mut stream)
+                .with_limits(limits)
+                .read_request(REQUEST_TIMEOUT)
+        };
+
+        let request = read_request(
+            "POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\n0123456789",
+        )
+        .unwrap();
+        assert_eq!(request.content().get_body(), b"0123456789");
+        assert_eq!(
+            get_error(read_request(
+                "POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 11\r\n\r\n0123456789A"
+            )),
+            InternalHttpError::KnownError(ErrorCode::ContentTooLarge)
+        );
+        assert_eq!(
+            get_error(read_request(&format!(
+                "GET / HTTP/1.1\r\nHost: localhost\r\nX-Long: {}\r\n\r\n",
+                "a".repeat(32)
+            ))),
+            InternalHttpError::KnownError(ErrorCode::RequestHeaderFieldsTooLarge)
+        );
+    }
+
+    #[test]
+    fn request_buffered_body_limit() {
+        let limits = RequestLimits {
+            max_body_size: 100,
+            max_buffered_body_size: 10,
+            ..Default::default()
+        };
+        let upload = |headers: &str| {
+            let request = format!(
+                "POST /upload HTTP/1.1\r\nHost: localhost\r\n{}Content-Length: 11\r\n\r\n0123456789A",
+                headers
+            );
+            Cursor::new(request.into_bytes())
+        };
+
+        // Plain uploads are left in the stream, only the body size limit applies to them
+        let mut stream = upload("");
+        let mut request_reader = HttpRequestReader::new(&mut stream).with_limits(limits);
+        request_reader.read_request_head(REQUEST_TIMEOUT).unwrap();
+        assert!(request_reader.has_pending_body());
+
+        let mut stream = upload("");
+        assert_eq!(
+            get_error(
+                HttpRequestReader::new(&mut stream)
+                    .with_limits(limits)
+                    .read_request(REQUEST_TIMEOUT)
+            ),
+            InternalHttpError::KnownError(ErrorCode::ContentTooLarge)
+        );
+
+        let mut stream = upload("Content-Type: multipart/form-data; boundary=abc\r\n");
+        assert_eq!(
+            get_error(
+                HttpRequestReader::new(&mut stream)
+                    .with_limits(limits)
+                    .read_request_head(REQUEST_TIMEOUT)
+            ),
+            InternalHttpError::KnownError(ErrorCode::ContentTooLarge)
+        );
+    }
+
+    #[test]
+    fn request_compressed_body_limit() {
+        let limits = RequestLimits {
+            max_body_size: 100,
+            ..Default::default()
+        };
+        let read_request = |body: &[u8]| {
+            let compressed = Compressor::compress(body, ContentEncoding::Gzip).unwrap();
+            let mut request = format!(
+                "POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+                compressed.len()
+            )
+            .into_bytes();
+            request.extend_from_slice(&compressed);
+            let mut stream = Cursor::new(request);
+            HttpRequestReader::new(&mut stream)
+                .with_limits(limits)
+                .read_request(REQUEST_TIMEOUT)
+        };
+
+        let request = read_request(&[b'a'; 100]).unwrap();
+        assert_eq!(request.content().get_body(), &[b'a'; 100]);
+
+        // A few bytes of zeros inflate to much more than the limit
+        assert_eq!(
+            get_error(read_request(&[0; 1024 * 1024])),
+            InternalHttpError::KnownError(ErrorCode::ContentTooLarge)
+        );
+    }
+
+    #[test]
+    fn request_length_required() {
+        for method in ["POST", "PUT"] {
+            assert_eq!(
+                get_error(parse_request(&format!(
+                    "{} /upload HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                    method
+                ))),
+                InternalHttpError::KnownError(ErrorCode::LengthRequired)
+            );
+        }
+
+        let request = parse_request(
+            "POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nHello",
+        )
+        .unwrap();
+        assert_eq!(request.content().get_body(), b"Hello");
+    }
+
+    #[test]
+    fn request_control_characters_in_target() {
+        for request in [
+            "GET /file.txt%00.png HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "GET /a%0d%0ab HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "GET /file\x01.txt HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            "GET /file.txt?\x7f HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        ] {
+            assert_eq!(
+                get_error(parse_request(request)),
+                InternalHttpError::KnownError(ErrorCode::BadRequest),
+                "{:?}",
+                request
+            );
+        }
+
+        // Decoded control characters are only a problem in the path
+        let request =
+            parse_request("GET /search?q=a%0Ab HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        assert_eq!(request.get_url().query_param("q").unwrap(), "a\nb");
+    }
+
+    #[test]
+    fn request_host_required() {
+        assert_eq!(
+            get_error(parse_request("GET / HTTP/1.1\r\nAccept: */*\r\n\r\n")),
+            InternalHttpError::KnownError(ErrorCode::BadRequest)
+        );
+
+        let request = parse_request("GET / HTTP/1.1\r\nHost: example.com\r\n\r\n").unwrap();
+        assert_eq!(request.content().get_header("host").unwrap(), "example.com");
+
+        let request = parse_request("GET / HTTP/1.0\r\n\r\n").unwrap();
+        assert!(request.content().get_header("host").is_none());
+    }
+
+    #[test]
+    fn request_host() {
+        let host = |request: &str| parse_request(request).unwrap().host();
+
+        assert_eq!(
+            host("GET / HTTP/1.1\r\nHost: Example.com\r\n\r\n").unwrap(),
+            (String::from("example.com"), None)
+        );
+        assert_eq!(
+            host("GET / HTTP/1.1\r\nHost: example.com:8080\r\n\r\n").unwrap(),
+            (String::from("example.com"), Some(8080))
+        );
+        assert_eq!(
+            host("GET / HTTP/1.1\r\nHost: [::1]:443\r\n\r\n").unwrap(),
+            (String::from("::1"), Some(443))
+        );
+        assert_eq!(
+            host("GET http://example.org:81/a HTTP/1.1\r\nHost: example.com\r\n\r\n").unwrap(),
+            (String::from("example.org"), Some(81))
+        );
+        assert!(host("GET / HTTP/1.1\r\nHost: example.com:http\r\n\r\n").is_none());
+        assert!(host("GET / HTTP/1.1\r\nHost: [::1\r\n\r\n").is_none());
+        assert!(host("GET / HTTP/1.0\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn request_absolute_form() {
+        let request =
+            parse_request("GET http://example.com/path HTTP/1.1\r\nHost: example.com\r\n\r\n")
+                .unwrap();
+
+        assert_eq!(request.get_url().resource(), "/path");
+        assert_eq!(request.host().unwrap(), (String::from("example.com"), None));
+    }
+
+    #[test]
+    fn request_reader_streamed_body() {
+        let mut stream = Cursor::new(
+            b"PUT /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nHelloPUT /gzip HTTP/1.1\r\nHost: localhost\r\n\
+              Content-Encoding: gzip\r\nContent-Length: 3\r\n\r\nabc"
+                .to_vec(),
+        );
+        let mut request_reader = HttpRequestReader::new(&mut stream);
+
+        let request = request_reader.read_request_head(REQUEST_TIMEOUT).unwrap();
+        assert!(request.content().get_body().is_empty());
+        assert!(request_reader.has_pending_body());
+        let mut body = Vec::new();
+        request_reader.body().read_to_end(&mut body).unwrap();
+        assert_eq!(body, b"Hello");
+        assert!(!request_reader.has_pending_body());
+
+        // Encoded bodies are decoded in memory, the truncated one can't be read at all
+        assert!(request_reader.read_request_head(REQUEST_TIMEOUT).is_err());
+        assert!(!request_reader.has_pending_body());
+    }
+
+    #[test]
+    fn request_streamed_body_cut_short() {
+        let mut stream = Cursor::new(
+            b"PUT /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\nHello".to_vec(),
+        );
+        let mut request_reader = HttpRequestReader::new(&mut stream);
+        request_reader.read_request_head(REQUEST_TIMEOUT).unwrap();
+
+        let mut body = Vec::new();
+        let error = request_reader.body().read_to_end(&mut body).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn request_parse_suffix_range() {
+        let request =
+            parse_request("GET / HTTP/1.1\r\nHost: localhost\r\nRange: bytes=-500\r\n\r\n")
+                .unwrap();
+        let ranges = request.ranges().unwrap();
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges.resolve(2000), vec![Range::new(1500, 1999)]);
+        assert_eq!(ranges.resolve(100), vec![Range::new(0, 99)]);
+        assert!(ranges.resolve(0).is_empty());
+
+        // Valid, but never satisfiable
+        let request =
+            parse_request("GET / HTTP/1.1\r\nHost: localhost\r\nRange: bytes=-0\r\n\r\n").unwrap();
+        assert!(request.ranges().unwrap().resolve(2000).is_empty());
+
+        for invalid_range in ["bytes=-", "bytes=--5"] {
+            let result = parse_request(&format!(
+                "GET / HTTP/1.1\r\nHost: localhost\r\nRange: {}\r\n\r\n",
+                invalid_range
+            ));
+            assert_eq!(
+                get_error(result),
+                InternalHttpError::KnownError(ErrorCode::BadRequest),
+                "{}",
+                invalid_range
+            );
+        }
+    }
+
+    #[test]
+    fn request_parse_open_ended_range() {
+        let request =
+            parse_request("GET / HTTP/1.1\r\nHost: localhost\r\nRange: bytes=500-\r\n\r\n")
+                .unwrap();
+        let ranges = request.ranges().unwrap();
+        assert_eq!(ranges.to_string(), "500-");
+        assert_eq!(ranges.resolve(2000), vec![Range::new(500, 1999)]);
+        assert!(ranges.resolve(500).is_empty());
+
+        assert_eq!(
+            get_error(parse_request(
+                "GET / HTTP/1.1\r\nHost: localhost\r\nRange: bytes=x-\r\n\r\n"
+            )),
+            InternalHttpError::KnownError(ErrorCode::BadRequest)
+        );
+    }
+
+    #[test]
+    fn request_range_unit() {
+        let request =
+            parse_request("GET / HTTP/1.1\r\nHost: localhost\r\nRange: items=0-1\r\n\r\n").unwrap();
+        assert!(request.ranges().is_none());
+
+        let request =
+            parse_request("GET / HTTP/1.1\r\nHost: localhost\r\nRange: Bytes=0-1\r\n\r\n").unwrap();
+        assert_eq!(request.ranges().unwrap().len(), 1);
+
+        assert_eq!(
+            get_error(parse_request(
+                "GET / HTTP/1.1\r\nHost: localhost\r\nRange: bytes=abc\r\n\r\n"
+            )),
+            InternalHttpError::KnownError(ErrorCode::BadRequest)
+        );
+    }
+
     #[test]
     fn request_invalid_utf_char() {
         let broken_heart: Vec<u8> = vec![240, 159, 146, 69];
         let invalid_utf_string = unsafe { String::from_utf8_unchecked(broken_heart) };
 
-        let request = format!("GET / HTTP/1.1\r\nLove:{}", invalid_utf_string);
+        let request = format!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nLove:{}",
+            invalid_utf_string
+        );
         let res = parse_request(request.as_str());
         assert!(res.is_err());
         assert_eq!(get_error(res), InternalHttpError::InvalidUTF8Char);
@@ -467,7 +1579,7 @@ mod test {
 
     #[test]
     fn request_parse_max_allowed_headers() {
-        let mut request = String::from("GET / HTTP/1.1\r\n");
+        let mut request = String::from("GET / HTTP/1.1\r\nHost: localhost\r\n");
         for _ in 0..MAX_HEADERS_AMOUNT {
             let header_name = get_random_string(10);
             let header_value = get_random_string(10);
@@ -483,6 +1595,89 @@ mod test {
         assert_eq!(get_error(result), InternalHttpError::HeaderOverflow);
     }
 
+    #[test]
+    fn request_timeout_on_stalled_stream() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        // Never send the terminating \r\n, the client stays connected until the end of the test
+        client.write_all(b"GET / HTTP/1.1").unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+
+        let timeout = Duration::from_millis(200);
+        let start = Instant::now();
+        let result = parse_http_request_with_timeout(&mut stream, timeout);
+
+        assert!(start.elapsed() < timeout * 10);
+        assert_eq!(
+            get_error(result),
+            InternalHttpError::KnownError(ErrorCode::RequestTimeout)
+        );
+    }
+
+    #[test]
+    fn request_expect_continue() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client
+            .write_all(
+                b"POST /upload HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n",
+            )
+            .unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        let server = std::thread::spawn(move || parse_http_request(&mut stream));
+
+        // The body is only sent once the server asked for it
+        let mut interim_response = vec![0; CONTINUE_RESPONSE.len()];
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client.read_exact(&mut interim_response).unwrap();
+        assert_eq!(interim_response, CONTINUE_RESPONSE);
+        client.write_all(b"Hello").unwrap();
+
+        let request = server.join().unwrap().unwrap();
+        assert_eq!(request.content().get_body(), b"Hello");
+    }
+
+    #[test]
+    fn request_expect_continue_rejected() {
+        let mut stream = Cursor::new(
+            format!(
+                "POST /upload HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: {}\r\n\r\n",
+                MAX_REQUEST_BODY_SIZE + 1
+            )
+            .into_bytes(),
+        );
+        let input_len = stream.get_ref().len();
+        let result = parse_http_request(&mut stream);
+
+        assert_eq!(
+            get_error(result),
+            InternalHttpError::KnownError(ErrorCode::ContentTooLarge)
+        );
+        // Nothing was written back, the caller sends the final response
+        assert_eq!(stream.get_ref().len(), input_len);
+    }
+
+    #[test]
+    fn request_expect_unsupported() {
+        let mut stream = Cursor::new(
+            b"POST /upload HTTP/1.1\r\nHost: localhost\r\nExpect: bogus\r\nContent-Length: 5\r\n\r\nHello"
+                .to_vec(),
+        );
+        assert_eq!(
+            get_error(parse_http_request(&mut stream)),
+            InternalHttpError::KnownError(ErrorCode::ExpectationFailed)
+        );
+
+        let mut stream = Cursor::new(
+            b"POST /upload HTTP/1.1\r\nHost: localhost\r\nExpect: 100-Continue\r\nContent-Length: 5\r\n\r\nHello"
+                .to_vec(),
+        );
+        let request = parse_http_request(&mut stream).unwrap();
+        assert_eq!(request.content().get_body(), b"Hello");
+    }
+
     static CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ\
                             abcdefghijklmnopqrstuvwxyz\
                             0123456789)(*&^%$#@!~";
@@ -0,0 +1,86 @@
+use std::cell::RefCell;
+
+use rand::Rng;
+
+use crate::request::HttpRequest;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+// Longer IDs of clients are replaced, they end up in every log line
+const MAX_REQUEST_ID_LENGTH: usize = 64;
+
+thread_local! {
+    static CURRENT_REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+// 16 hex digits
+pub fn generate() -> String {
+    format!("{:016x}", rand::thread_rng().gen::<u64>())
+}
+
+// IDs of clients are kept as long as they're short and only made of visible characters
+pub fn from_request(request: &HttpRequest) -> Option<String> {
+    request
+        .content()
+        .get_header(REQUEST_ID_HEADER)
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LENGTH
+                && id.bytes().all(|byte| byte.is_ascii_graphic())
+        })
+        .cloned()
+}
+
+// ID of the request the current thread is handling, for log lines
+pub fn current() -> Option<String> {
+    CURRENT_REQUEST_ID.with(|id| id.borrow().clone())
+}
+
+// The ID is the current one until the scope is dropped
+#[derive(Debug)]
+pub struct RequestIdScope {
+    id: String,
+}
+
+impl RequestIdScope {
+    pub fn enter(id: String) -> Self {
+        CURRENT_REQUEST_ID.with(|current| *current.borrow_mut() = Some(id.clone()));
+        Self { id }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn replace(&mut self, id: String) {
+        CURRENT_REQUEST_ID.with(|current| *current.borrow_mut() = Some(id.clone()));
+        self.id = id;
+    }
+}
+
+impl Drop for RequestIdScope {
+    fn drop(&mut self) {
+        CURRENT_REQUEST_ID.with(|current| current.borrow_mut().take());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_id_scope() {
+        assert!(current().is_none());
+        {
+            let mut scope = RequestIdScope::enter(String::from("first"));
+            assert_eq!(current().unwrap(), "first");
+            scope.replace(String::from("second"));
+            assert_eq!(scope.id(), "second");
+            assert_eq!(current().unwrap(), "second");
+        }
+        assert!(current().is_none());
+
+        let id = generate();
+        assert_eq!(id.len(), 16);
+        assert!(id.bytes().all(|byte| byte.is_ascii_hexdigit()));
+    }
+}
@@ -1,24 +1,30 @@
 use std::{
-    cmp,
     collections::HashMap,
+    fmt,
     fs::{self, File},
-    io::Write,
-    os::windows::fs::FileExt,
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
 };
 
 use crate::{
-    auth::Authenticator,
+    access_log::raw_response_status_and_body_size,
+    auth::AuthMethod,
+    cache::CachedResponse,
     common::*,
-    compressor::{Compressor, ContentEncoding},
+    compressor::{Compressor, ContentEncoding, MIN_COMPRESSION_SIZE},
+    config::{ServerConfig, DEFAULT_SERVER_HEADER},
+    multipart::MultipartForm,
     request::{HttpRequest, HttpRequestMethod},
 };
 
 use anyhow::{Error, Result};
-use chrono::Utc;
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
 use log::{error, trace};
+use serde::Serialize;
 
-impl ToString for ResponseCode {
-    fn to_string(&self) -> String {
+impl fmt::Display for ResponseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         fn split_camel_case(s: String) -> String {
             let mut result = String::new();
             for (i, c) in s.chars().enumerate() {
@@ -29,14 +35,15 @@ impl ToString for ResponseCode {
             }
             result
         }
-        match self {
+        let reason = match self {
             ResponseCode::Success(code) => match code {
                 SuccessCode::Ok => "OK".to_string(),
                 _ => split_camel_case(format!("{:?}", code)),
             },
 
             ResponseCode::Error(code) => split_camel_case(format!("{:?}", code)),
-        }
+        };
+        write!(f, "{}", reason)
     }
 }
 
@@ -46,28 +53,139 @@ pub struct HttpResponse {
     version: String,
     content: HttpMessageContent,
     encoding: Option<ContentEncoding>,
+    // Send the body with `transfer-encoding: chunked` rather than `content-length`
+    streaming: bool,
+    // Copied straight from the file while writing, rather than kept in `content`
+    file_body: Option<FileBody>,
+}
+
+#[derive(Debug)]
+struct FileBody {
+    file: File,
+    parts: Vec<FilePart>,
+}
+
+// Body of a file response, sections of the file and the framing around them
+#[derive(Debug)]
+enum FilePart {
+    Bytes(Vec<u8>),
+    Section { from: u64, len: u64 },
+}
+
+impl FilePart {
+    fn range(range: &Range) -> Self {
+        // Both ends of the range are inclusive
+        FilePart::Section {
+            from: range.from,
+            len: range.to - range.from + 1,
+        }
+    }
+
+    fn len(&self) -> u64 {
+        match self {
+            FilePart::Bytes(bytes) => bytes.len() as u64,
+            FilePart::Section { len, .. } => *len,
+        }
+    }
+}
+
+impl FileBody {
+    fn len(&self) -> u64 {
+        self.parts.iter().map(FilePart::len).sum()
+    }
+}
+
+// Files at least this large are streamed from disk rather than read into memory
+pub const STREAMING_THRESHOLD: u64 = 1024 * 1024; // 1 MB
+
+// What was sent for a request, the same for built and cached responses
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResponseSummary {
+    pub status_code: u16,
+    pub headers: HashMap<String, String>,
+    pub body_size: u64,
+}
+
+impl ResponseSummary {
+    pub fn from_raw(raw_response: &[u8]) -> Option<Self> {
+        let (status_code, body_size) = raw_response_status_and_body_size(raw_response)?;
+        let head = String::from_utf8_lossy(raw_response);
+        let headers = head
+            .split("\r\n")
+            .skip(1)
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                Some((name.trim().to_ascii_lowercase(), value.trim().to_string()))
+            })
+            .collect();
+        Some(Self {
+            status_code,
+            headers,
+            body_size,
+        })
+    }
+}
+
+impl From<&CachedResponse> for ResponseSummary {
+    fn from(response: &CachedResponse) -> Self {
+        Self {
+            status_code: response.status_code,
+            headers: response.headers.clone(),
+            body_size: response.body.len() as u64,
+        }
+    }
 }
 
-pub struct HttpResponseBuilder(HttpResponse);
+impl From<&HttpResponse> for ResponseSummary {
+    fn from(response: &HttpResponse) -> Self {
+        Self {
+            status_code: response.status_code.get_code_value(),
+            headers: response.content.get_headers().clone(),
+            body_size: response.body_size(),
+        }
+    }
+}
+
+pub struct HttpResponseBuilder {
+    response: HttpResponse,
+    min_compression_size: usize,
+}
 impl HttpResponseBuilder {
     pub fn new(
         status_code: ResponseCode,
         version: &str,
         encoding: Option<ContentEncoding>,
     ) -> Self {
-        let builder = Self(HttpResponse {
-            status_code,
-            version: String::from(version),
-            content: HttpMessageContent::new(HashMap::new(), Vec::new()),
-            encoding,
-        })
+        let mut builder = Self {
+            response: HttpResponse {
+                status_code,
+                version: String::from(version),
+                content: HttpMessageContent::new(HashMap::new(), Vec::new()),
+                encoding,
+                streaming: false,
+                file_body: None,
+            },
+            min_compression_size: MIN_COMPRESSION_SIZE,
+        }
         // General purpose headers
-        .header("accept-ranges", "bytes")
-        .header(
-            "date",
-            Utc::now().format("%a, %d %b %Y %H:%M:%S GMT").to_string(),
-        )
-        .header("server", "simple http");
+        .header("accept-ranges", "bytes");
+
+        // Overwritten once the body is set, keeps empty responses delimited on persistent connections.
+        // 101, 204 and 304 never have a body, content-length isn't allowed in 101 and 204 and would
+        // describe the unchanged resource in 304
+        if !matches!(
+            status_code,
+            ResponseCode::Success(
+                SuccessCode::SwitchingProtocols | SuccessCode::NoContent | SuccessCode::NotModified
+            )
+        ) {
+            builder = builder.header("content-length", "0");
+        }
+
+        let builder = builder
+            .header("date", Utc::now().format(HTTP_DATE_FORMAT).to_string())
+            .header("server", DEFAULT_SERVER_HEADER);
 
         if let Some(encoding) = encoding {
             builder.header("content-encoding", encoding.to_string())
@@ -85,77 +203,340 @@ impl HttpResponseBuilder {
         header_name: impl Into<String>,
         header_content: impl Into<String>,
     ) -> Self {
-        self.0
+        self.response
             .content
             .add_header(header_name.into(), header_content.into());
         self
     }
 
-    // TODO: don't read body for the head request, just query the length of resource
+    // HEAD responses get the headers of GET, compression included, just without the body
     pub fn optional_body(self, body: &[u8], include_body: bool) -> Self {
-        if include_body {
-            self.body(body)
+        let mut builder = self.body(body);
+        if !include_body {
+            builder.response.content.set_body(Vec::new());
+        }
+        builder
+    }
+
+    // Bodies smaller than this are sent as is, as compression framing would only make them larger
+    pub fn min_compression_size(mut self, min_compression_size: usize) -> Self {
+        self.min_compression_size = min_compression_size;
+        self
+    }
+
+    pub fn streaming(mut self, streaming: bool) -> Self {
+        // HTTP/1.0 clients don't understand chunked transfer-encoding
+        let streaming = streaming && self.response.version != "1.0";
+        self.response.streaming = streaming;
+        if streaming {
+            self.response.content.remove_header("content-length");
+            self.header("transfer-encoding", "chunked")
         } else {
-            self.header("content-length", body.len().to_string())
+            self.response.content.remove_header("transfer-encoding");
+            let body_length = self.response.content.get_body().len();
+            self.header("content-length", body_length.to_string())
         }
     }
 
-    pub fn body(mut self, body: &[u8]) -> Self {
-        if let Some(content_encoding) = self.0.encoding {
-            self.0
-                .content
-                .set_body(Compressor::compress(body, content_encoding));
+    pub fn redirect(mut self, location: impl Into<String>, permanent: bool) -> Self {
+        self.response.status_code = if permanent {
+            ResponseCode::Success(SuccessCode::MovedPermanently)
         } else {
-            self.0.content.set_body(Vec::from(body));
+            ResponseCode::Success(SuccessCode::Found)
         };
+        self.header("location", location)
+    }
+
+    // The body was compressed ahead of time, like a `.gz` file next to the requested one
+    pub fn precompressed_body(mut self, body: &[u8], encoding: ContentEncoding) -> Self {
+        self = self.without_encoding();
+        self.response.content.set_body(Vec::from(body));
+        self.response.add_vary("Accept-Encoding");
+        self.header("content-encoding", encoding.to_string())
+            .header("content-length", body.len().to_string())
+    }
+
+    fn without_encoding(mut self) -> Self {
+        self.response.encoding = None;
+        self.response.content.remove_header("content-encoding");
+        self
+    }
 
-        let body_length = self.0.content.get_body().len();
+    pub fn body(mut self, body: &[u8]) -> Self {
+        let is_incompressible = self
+            .response
+            .content
+            .get_header("content-type")
+            .is_some_and(|content_type| !Compressor::is_compressible(content_type));
+        if is_incompressible || body.len() < self.min_compression_size {
+            self = self.without_encoding();
+        }
+
+        let compressed_body = self
+            .response
+            .encoding
+            .map(|content_encoding| Compressor::compress(body, content_encoding));
+
+        match compressed_body {
+            Some(Ok(compressed_body)) => {
+                self.response.content.set_body(compressed_body);
+                // Shared caches have to keep the compressed body apart from the identity one
+                self.response.add_vary("Accept-Encoding");
+            }
+            Some(Err(err)) => {
+                error!("Sending uncompressed body: {:?}", err);
+                self = self.without_encoding();
+                self.response.content.set_body(Vec::from(body));
+            }
+            None => self.response.content.set_body(Vec::from(body)),
+        }
+
+        if self.response.streaming {
+            return self;
+        }
+        let body_length = self.response.content.get_body().len();
         self.header("content-length", body_length.to_string())
     }
 
-    pub fn build(self) -> HttpResponse {
-        self.0
+    // Serialization only fails for values JSON can't represent, like maps with non-string keys
+    pub fn json<T: Serialize>(mut self, value: &T) -> Self {
+        match serde_json::to_vec(value) {
+            Ok(body) => self.header("content-type", "application/json").body(&body),
+            Err(err) => {
+                error!("Failed to serialize JSON body: {:?}", err);
+                self.response.status_code = ResponseCode::Error(ErrorCode::InternalServerError);
+                self
+            }
+        }
+    }
+
+    // `Expires` is the `Date` of the response plus max-age, for caches that only know HTTP/1.0
+    pub fn max_age(self, max_age: Duration) -> Self {
+        let date = self
+            .response
+            .content
+            .get_header("date")
+            .and_then(|date| NaiveDateTime::parse_from_str(date, HTTP_DATE_FORMAT).ok())
+            .map_or_else(Utc::now, |date| date.and_utc());
+        let expires = TimeDelta::try_seconds(max_age.as_secs() as i64)
+            .and_then(|max_age| date.checked_add_signed(max_age));
+
+        let builder = self.header("cache-control", format!("max-age={}", max_age.as_secs()));
+        match expires {
+            Some(expires) => {
+                builder.header("expires", expires.format(HTTP_DATE_FORMAT).to_string())
+            }
+            None => builder,
+        }
+    }
+
+    // Large files are sent as is, compressing them would need the whole content in memory
+    pub fn file_body(self, file: File, len: u64) -> Self {
+        self.file_parts(file, vec![FilePart::Section { from: 0, len }])
+    }
+
+    fn file_parts(mut self, file: File, parts: Vec<FilePart>) -> Self {
+        self = self.without_encoding().streaming(false);
+        let file_body = FileBody { file, parts };
+        let len = file_body.len();
+        self.response.file_body = Some(file_body);
+        self.header("content-length", len.to_string())
+    }
+
+    pub fn build(mut self) -> HttpResponse {
+        // Compressed bodies are representations of their own, caches mustn't mix them up with the
+        // identity one by the ETag
+        let content = &mut self.response.content;
+        let encoded_etag = content
+            .get_header("content-encoding")
+            .filter(|encoding| *encoding != "identity")
+            .zip(content.get_header("etag"))
+            .map(|(encoding, etag)| encoded_etag(etag, encoding));
+        if let Some(encoded_etag) = encoded_etag {
+            content.add_header("etag", encoded_etag);
+        }
+        self.response
     }
 }
 
 impl HttpResponse {
+    // The head and the chunk framing are small writes, they're batched before reaching the stream
     pub fn write_to(&self, stream: &mut impl HttpStream) -> Result<()> {
-        stream.write_all(&self.as_bytes())?;
+        let mut writer = BufWriter::with_capacity(CHUNK_SIZE, stream);
+        self.write_internal(&mut writer)?;
+        writer.flush()?;
         Ok(())
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
         let mut response = Vec::new();
-        response.extend_from_slice(
-            format!(
-                "HTTP/{} {} {}\r\n",
-                self.version,
-                self.status_code.get_code_value(),
-                self.status_code.to_string()
-            )
-            .as_bytes(),
-        );
+        self.write_internal(&mut response)
+            .expect("Writing to a vector can't fail");
+        response
+    }
+
+    fn write_internal(&self, writer: &mut impl Write) -> io::Result<()> {
+        write!(
+            writer,
+            "HTTP/{} {} {}\r\n",
+            self.version,
+            self.status_code.get_code_value(),
+            self.status_code
+        )?;
 
         for (header_name, header_content) in self.content.get_headers() {
-            response
-                .extend_from_slice(format!("{}: {}\r\n", header_name, header_content).as_bytes());
+            write!(writer, "{}: {}\r\n", header_name, header_content)?;
         }
+        writer.write_all(b"\r\n")?;
 
-        response.extend_from_slice(b"\r\n");
-        response.extend_from_slice(&self.content.get_body());
-        response
+        if let Some(file_body) = &self.file_body {
+            HttpResponse::write_file(writer, file_body)
+        } else if self.streaming {
+            HttpResponse::write_chunked(writer, self.content.get_body())
+        } else {
+            writer.write_all(self.content.get_body())
+        }
+    }
+
+    fn write_file(writer: &mut impl Write, file_body: &FileBody) -> io::Result<()> {
+        write_file_parts(writer, &file_body.file, &file_body.parts)
+    }
+
+    // Size of the body as sent, without the chunked framing
+    pub fn body_size(&self) -> u64 {
+        match &self.file_body {
+            Some(file_body) => file_body.len(),
+            None => self.content.get_body().len() as u64,
+        }
+    }
+
+    pub fn has_file_body(&self) -> bool {
+        self.file_body.is_some()
+    }
+
+    fn write_chunked(writer: &mut impl Write, body: &[u8]) -> io::Result<()> {
+        for chunk in body.chunks(CHUNK_SIZE) {
+            write!(writer, "{:x}\r\n", chunk.len())?;
+            writer.write_all(chunk)?;
+            writer.write_all(b"\r\n")?;
+        }
+        // Last chunk, without trailers
+        writer.write_all(b"0\r\n\r\n")
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn status_code(&self) -> ResponseCode {
+        self.status_code
     }
 
     pub fn content(&self) -> &HttpMessageContent {
         &self.content
     }
 
+    pub fn add_header(
+        &mut self,
+        header_name: impl Into<String>,
+        header_content: impl Into<String>,
+    ) -> Option<String> {
+        self.content.add_header(header_name, header_content)
+    }
+
+    // Adds a request header the response depends on to the ones already listed in `Vary`
+    pub fn add_vary(&mut self, header_name: &str) {
+        let vary = match self.content.get_header("vary") {
+            Some(vary)
+                if vary
+                    .split(',')
+                    .any(|listed| listed.trim().eq_ignore_ascii_case(header_name)) =>
+            {
+                return;
+            }
+            Some(vary) => format!("{}, {}", vary, header_name),
+            None => String::from(header_name),
+        };
+        self.add_header("vary", vary);
+    }
+
+    // Replaces the default `Server` header, `None` removes it
+    pub fn set_server_header(&mut self, server_header: Option<&str>) {
+        match server_header {
+            Some(server_header) => self.add_header("server", server_header),
+            None => self.content.remove_header("server"),
+        };
+    }
+
+    // Keep browsers from guessing the content type, framing the page and loading foreign content
+    pub fn add_security_headers(&mut self, content_security_policy: &str) {
+        self.add_header("x-content-type-options", "nosniff");
+        self.add_header("x-frame-options", "DENY");
+        if !content_security_policy.is_empty() {
+            self.add_header("content-security-policy", content_security_policy);
+        }
+    }
+
+    // Error responses without a body of their own get one that describes the error
+    fn add_error_body(&mut self, http_request: Option<&HttpRequest>, include_body: bool) {
+        if !matches!(self.status_code, ResponseCode::Error(_)) || self.body_size() != 0 {
+            return;
+        }
+        let (content_type, body) = error_body(self.status_code, http_request);
+        self.encoding = None;
+        self.content.remove_header("content-encoding");
+        self.add_header("content-type", content_type);
+        self.add_header("content-length", body.len().to_string());
+        if include_body {
+            self.content.set_body(body.into_bytes());
+        }
+    }
+
     pub fn partial_content_boundary<'life>() -> &'life str {
         "3d6b6a416f9b5"
     }
 }
 
+#[derive(Serialize)]
+struct ErrorBody {
+    error: String,
+    code: u16,
+}
+
+const ERROR_BODY_MEDIA_TYPES: [&str; 3] = ["application/json", "text/html", "text/plain"];
+
+// Like `404 Not Found`, in the format the client prefers. Plain text when it has no preference
+// or the request couldn't even be parsed
+fn error_body(status_code: ResponseCode, http_request: Option<&HttpRequest>) -> (&str, String) {
+    let code = status_code.get_code_value();
+    let status = format!("{} {}", code, status_code);
+    match http_request.and_then(|request| request.preferred_media_type(&ERROR_BODY_MEDIA_TYPES)) {
+        Some("application/json") => (
+            "application/json",
+            serde_json::to_string(&ErrorBody {
+                error: status_code.to_string(),
+                code,
+            })
+            .expect("Serializing the error can't fail"),
+        ),
+        Some("text/html") => (
+            "text/html",
+            format!(
+                "<!DOCTYPE html>\n<html>\n<head><title>{0}</title></head>\n<body><h1>{0}</h1></body>\n</html>\n",
+                status
+            ),
+        ),
+        _ => ("text/plain", status),
+    }
+}
+
 pub fn build_http_response_for_invalid_request(mb_http_error: Error) -> HttpResponse {
+    let mut response = build_response_for_invalid_request(mb_http_error);
+    response.add_error_body(None, true);
+    response
+}
+
+fn build_response_for_invalid_request(mb_http_error: Error) -> HttpResponse {
     if let Some(http_error) = mb_http_error.downcast_ref::<InternalHttpError>() {
         match http_error {
             InternalHttpError::KnownError(http_error_code) => {
@@ -169,51 +550,233 @@ pub fn build_http_response_for_invalid_request(mb_http_error: Error) -> HttpResp
     }
 }
 
-fn read_file_content(file: &File, content_range: Option<Ranges>) -> Result<Vec<u8>> {
-    let range = match content_range {
-        Some(ranges) if !ranges.is_multipart() => {
-            let first = ranges.first().unwrap();
-            Range::new(first.from, first.to)
-        }
-        _ => Range::new(0, file.metadata()?.len()),
-    };
-    let body_size = (range.to - range.from) as usize;
-    let mut file_content = vec![0; body_size];
-    let bytes_read = file.seek_read(&mut file_content, range.from)?;
-    debug_assert!(bytes_read == body_size);
+// Changes whenever the file is modified or resized
+fn file_etag(metadata: &fs::Metadata) -> Option<String> {
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(format!(
+        "\"{:x}-{:x}\"",
+        modified.as_nanos(),
+        metadata.len()
+    ))
+}
 
-    Ok(file_content)
+// The encoding is appended to the ETag of the file, `"abc"` becomes `"abc-gzip"`
+fn encoded_etag(etag: &str, encoding: &str) -> String {
+    format!("{}-{}\"", etag.trim_end_matches('"'), encoding)
 }
 
-pub fn build_body_for_multipart_request(
-    ranges: &Ranges,
-    content_type: &str,
-    boundary: &str,
-    file_content: &Vec<u8>,
-) -> Vec<u8> {
-    let mut res: Vec<u8> = Vec::new();
+// `If-None-Match` uses the weak comparison, `W/"abc"` matches `"abc"`. ETags of compressed
+// representations match as well, the one that matched is sent back with 304
+fn matching_etag(if_none_match: &str, etag: &str) -> Option<String> {
+    let opaque_etag = etag.trim_start_matches("W/");
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .find_map(|candidate| {
+            if candidate == "*" {
+                return Some(etag.to_string());
+            }
+            let candidate = candidate.trim_start_matches("W/");
+            let is_encoded_etag = opaque_etag
+                .strip_suffix('"')
+                .and_then(|prefix| candidate.strip_prefix(prefix))
+                .and_then(|suffix| suffix.strip_prefix('-')?.strip_suffix('"'))
+                .is_some_and(|encoding| encoding.parse::<ContentEncoding>().is_ok());
+            (candidate == opaque_etag || is_encoded_etag).then(|| candidate.to_string())
+        })
+}
 
-    for range in ranges.elements() {
-        res.extend_from_slice(format!("--{}\r\n", boundary.to_string()).as_bytes());
-        res.extend_from_slice(format!("content-type: {}\r\n", content_type).as_bytes());
-        res.extend_from_slice(
-            format!("content-range: bytes {}-{}\r\n\r\n", range.from, range.to).as_bytes(),
-        );
+// HTTP dates have a one second precision
+fn last_modified(metadata: &fs::Metadata) -> Option<DateTime<Utc>> {
+    let modified = DateTime::<Utc>::from(metadata.modified().ok()?);
+    DateTime::from_timestamp(modified.timestamp(), 0)
+}
+
+fn modified_since(if_modified_since: &str, last_modified: &DateTime<Utc>) -> bool {
+    match NaiveDateTime::parse_from_str(if_modified_since.trim(), HTTP_DATE_FORMAT) {
+        Ok(since) => *last_modified > since.and_utc(),
+        // Invalid dates are ignored
+        Err(_) => true,
+    }
+}
+
+// Served files can be reused by clients and caches for the configured time
+fn with_max_age(builder: HttpResponseBuilder, config: &ServerConfig) -> HttpResponseBuilder {
+    match config.max_age {
+        Some(max_age) => builder.max_age(max_age),
+        None => builder,
+    }
+}
+
+// `If-Range` keeps the range only while the resource is unchanged. ETags use the strong
+// comparison, dates have to be the exact modification time
+fn if_range_matches(
+    if_range: &str,
+    etag: Option<&str>,
+    last_modified: Option<&DateTime<Utc>>,
+) -> bool {
+    let if_range = if_range.trim();
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        return etag.is_some_and(|etag| !etag.starts_with("W/") && etag == if_range);
+    }
+    match NaiveDateTime::parse_from_str(if_range, HTTP_DATE_FORMAT) {
+        Ok(date) => last_modified.is_some_and(|last_modified| *last_modified == date.and_utc()),
+        Err(_) => false,
+    }
+}
 
-        let from = range.from as usize;
-        let to = cmp::min((range.to + 1) as usize, file_content.len());
+// `..` segments would let a request escape the served directory
+fn is_path_traversal(resource: &str) -> bool {
+    resource.split(['/', '\\']).any(|segment| segment == "..")
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+// Characters that would end the path of a link or be taken for an escape sequence
+fn escape_link_segment(segment: &str) -> String {
+    segment
+        .replace('%', "%25")
+        .replace(' ', "%20")
+        .replace('?', "%3F")
+        .replace('#', "%23")
+}
+
+// Index file of the directory, if the resource is a directory that has one
+fn index_file_path(directory: &Path, index_file: &str) -> Option<PathBuf> {
+    if index_file.is_empty() || !directory.is_dir() {
+        return None;
+    }
+    let index_path = directory.join(index_file);
+    index_path.is_file().then_some(index_path)
+}
+
+// `<path>.gz` and the like, served as is when the client negotiated their encoding
+fn open_precompressed_file(
+    path: &Path,
+    encoding: Option<ContentEncoding>,
+    config: &ServerConfig,
+) -> Option<(File, u64, ContentEncoding)> {
+    let encoding = encoding?;
+    let mut precompressed_path = path.as_os_str().to_owned();
+    precompressed_path.push(format!(".{}", encoding.file_extension()?));
+    let precompressed_path = PathBuf::from(precompressed_path);
+    if !config.is_under_root(&precompressed_path)
+        || config.is_forbidden_symlink(&precompressed_path)
+    {
+        return None;
+    }
+
+    let file = File::open(&precompressed_path).ok()?;
+    let metadata = file.metadata().ok()?;
+    metadata
+        .is_file()
+        .then_some((file, metadata.len(), encoding))
+}
+
+// Simple HTML index of the directory, entries are sorted by name and link to `/<resource>/<entry>`
+fn directory_listing(directory: &Path, resource: &str) -> io::Result<String> {
+    let mut entries = fs::read_dir(directory)?
+        .map(|entry| {
+            let entry = entry?;
+            let mut name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type()?.is_dir() {
+                name.push('/');
+            }
+            Ok(name)
+        })
+        .collect::<io::Result<Vec<String>>>()?;
+    entries.sort();
 
-        res.extend_from_slice(&file_content[from..to]);
-        res.extend_from_slice(b"\r\n");
+    let title = html_escape(&format!("Index of /{}", resource));
+    let base = resource.trim_end_matches('/');
+    let mut listing = format!(
+        "<!DOCTYPE html>\n<html>\n<head><title>{title}</title></head>\n<body>\n<h1>{title}</h1>\n<ul>\n"
+    );
+    for name in entries {
+        let mut link = String::new();
+        for segment in base.split('/').chain([name.trim_end_matches('/')]) {
+            if !segment.is_empty() {
+                link.push('/');
+                link.push_str(&escape_link_segment(segment));
+            }
+        }
+        if name.ends_with('/') {
+            link.push('/');
+        }
+        listing.push_str(&format!(
+            "<li><a href=\"{}\">{}</a></li>\n",
+            html_escape(&link),
+            html_escape(&name)
+        ));
+    }
+    listing.push_str("</ul>\n</body>\n</html>\n");
+    Ok(listing)
+}
+
+// Sections are copied one at a time, the file is never read as a whole
+fn write_file_parts(
+    writer: &mut impl Write,
+    mut file: &File,
+    parts: &[FilePart],
+) -> io::Result<()> {
+    for part in parts {
+        match part {
+            FilePart::Bytes(bytes) => writer.write_all(bytes)?,
+            FilePart::Section { from, len } => {
+                file.seek(SeekFrom::Start(*from))?;
+                let copied = io::copy(&mut file.take(*len), writer)?;
+                if copied != *len {
+                    // The file was truncated in the meantime, the announced length can't be met
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// For bodies small enough to be held in memory
+fn read_file_parts(file: &File, parts: &[FilePart]) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    write_file_parts(&mut content, file, parts)?;
+    Ok(content)
+}
+
+// `total_len` is the length of the whole file, every part tells where it belongs
+fn multipart_parts(
+    ranges: &[Range],
+    content_type: &str,
+    boundary: &str,
+    total_len: u64,
+) -> Vec<FilePart> {
+    let mut parts = Vec::with_capacity(ranges.len() * 3);
+    for range in ranges {
+        let mut part_head = format!("--{}\r\n", boundary);
+        part_head.push_str(&format!("content-type: {}\r\n", content_type));
+        part_head.push_str(&format!(
+            "content-range: bytes {}-{}/{}\r\n\r\n",
+            range.from, range.to, total_len
+        ));
+        parts.push(FilePart::Bytes(part_head.into_bytes()));
+        parts.push(FilePart::range(range));
+        parts.push(FilePart::Bytes(b"\r\n".to_vec()));
     }
-    res
+    parts
 }
 
+// Large bodies are streamed from the file, like whole files are
 pub fn build_response_for_multipart_request(
     http_request: &HttpRequest,
-    file_content: &Vec<u8>,
-    ranges: &Ranges,
+    file: File,
+    ranges: &[Range],
     content_type: &str,
+    total_len: u64,
 ) -> HttpResponse {
     let partial_content_builder = HttpResponseBuilder::new(
         ResponseCode::Success(SuccessCode::PartialContent),
@@ -222,45 +785,256 @@ pub fn build_response_for_multipart_request(
     );
     let is_not_head_request = http_request.get_method() != HttpRequestMethod::HEAD;
 
-    if ranges.is_multipart() {
+    let (partial_content_builder, parts) = if ranges.len() > 1 {
         let boundary = HttpResponse::partial_content_boundary();
         let multipart_content_type = format!("multipart/byteranges; boundary={}", boundary);
+        (
+            partial_content_builder.header("content-type", multipart_content_type),
+            multipart_parts(ranges, content_type, boundary, total_len),
+        )
+    } else {
+        let range = &ranges[0];
+        (
+            partial_content_builder
+                .header("content-type", content_type)
+                .header(
+                    "content-range",
+                    format!("bytes {}-{}/{}", range.from, range.to, total_len),
+                ),
+            vec![FilePart::range(range)],
+        )
+    };
 
-        partial_content_builder
-            .header("content-type", multipart_content_type)
-            .optional_body(
-                &build_body_for_multipart_request(&ranges, &content_type, &boundary, &file_content),
-                is_not_head_request,
-            )
-            .build()
+    let body_len: u64 = parts.iter().map(FilePart::len).sum();
+    if !is_not_head_request {
+        return partial_content_builder
+            .header("content-length", body_len.to_string())
+            .build();
+    }
+    if body_len >= STREAMING_THRESHOLD {
+        return partial_content_builder.file_parts(file, parts).build();
+    }
+    match read_file_parts(&file, &parts) {
+        Ok(body) => partial_content_builder.body(&body).build(),
+        Err(err) => build_http_response_for_invalid_request(err.into()),
+    }
+}
+
+// Headers allowed for preflight requests that don't list the ones they need
+const CORS_ALLOWED_HEADERS: &str = "Authorization, Content-Type, Range";
+
+// Built-in routes are generated by the server, they can't be written or removed
+fn is_builtin_route(resource: &str) -> bool {
+    resource.is_empty() || resource == "user-agent" || resource.starts_with("echo/")
+}
+
+fn allowed_methods(resource: &str) -> Vec<String> {
+    if is_builtin_route(resource) {
+        ["GET", "HEAD", "OPTIONS", "TRACE"]
+            .into_iter()
+            .map(|val| val.to_string())
+            .collect()
     } else {
-        let range = ranges.first().unwrap();
+        HttpRequestMethod::supported_methods()
+    }
+}
 
-        partial_content_builder
-            .header("content-type", content_type)
-            .header(
-                "content-range",
-                format!("bytes {}-{}", range.from, range.to),
+// Credentials must not be reflected, a script that can read the echo could steal them
+const TRACE_EXCLUDED_HEADERS: [&str; 4] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+
+// The request as received, only header names are lowercase and their order is lost
+fn trace_body(http_request: &HttpRequest) -> String {
+    let url = http_request.get_url();
+    let mut target = url.resource();
+    if !url.query().is_empty() {
+        target = format!("{}?{}", target, url.query());
+    }
+    let mut body = format!(
+        "{:?} {} HTTP/{}\r\n",
+        http_request.get_method(),
+        target,
+        http_request.get_version()
+    );
+
+    let mut headers: Vec<_> = http_request
+        .content()
+        .get_headers()
+        .iter()
+        .filter(|(name, _)| !TRACE_EXCLUDED_HEADERS.contains(&name.as_str()))
+        .collect();
+    headers.sort();
+    for (name, value) in headers {
+        body.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    body.push_str("\r\n");
+    body
+}
+
+// Presented credentials are always verified, protected paths can't be accessed without them
+fn check_credentials(
+    http_request: &HttpRequest,
+    config: &ServerConfig,
+    resource: &str,
+) -> Option<HttpResponse> {
+    let authenticated = match http_request.auth_info() {
+        Some((auth_method, auth_data)) => config
+            .authenticator
+            .authenticate(auth_data.as_bytes(), auth_method),
+        None => !config.is_protected(resource),
+    };
+    if authenticated {
+        return None;
+    }
+
+    let auth_method = match http_request.auth_info() {
+        Some((auth_method, _)) => auth_method,
+        None => &AuthMethod::Basic,
+    };
+    let unauthorized_response = HttpResponseBuilder::new(
+        ResponseCode::Error(ErrorCode::Unauthorized),
+        &http_request.get_version(),
+        http_request.get_encoding(),
+    )
+    .header("WWW-Authenticate", auth_method.to_string())
+    .build();
+    Some(unauthorized_response)
+}
+
+// Headers that apply to every kind of response are added here.
+// Actual cross-origin responses only name the allowed origin, everything else was negotiated
+// during the preflight
+pub fn build_http_response(http_request: &HttpRequest, config: &ServerConfig) -> HttpResponse {
+    build_http_response_with_body(
+        http_request,
+        &mut http_request.content().get_body().as_slice(),
+        config,
+    )
+}
+
+// Uploads are copied from `body` instead of the body of the request, which is empty when the
+// body was left in the stream
+pub fn build_http_response_with_body(
+    http_request: &HttpRequest,
+    body: &mut dyn Read,
+    config: &ServerConfig,
+) -> HttpResponse {
+    let host = http_request.host().map(|(host, _)| host);
+    // Files are looked up in the fallback roots too, they're only written to the first root
+    let root = config.root_for_host(host.as_deref()).map(|root| {
+        match (
+            http_request.get_method(),
+            http_request.get_url().decoded_resource(),
+        ) {
+            (HttpRequestMethod::GET | HttpRequestMethod::HEAD, Some(resource)) => {
+                config.root_with_resource(root, &resource)
+            }
+            _ => root,
+        }
+    });
+    let mut response = match (config.router.handler(http_request), root) {
+        (Some(handler), _) => handler(http_request),
+        (None, Some(root)) if *root == config.root => {
+            build_resource_response(http_request, body, config)
+        }
+        (None, Some(root)) => {
+            let host_config = ServerConfig {
+                root: root.clone(),
+                ..config.clone()
+            };
+            build_resource_response(http_request, body, &host_config)
+        }
+        (None, None) => {
+            error!("No virtual host configured for {:?}", host);
+            HttpResponseBuilder::new(
+                ResponseCode::Error(ErrorCode::NotFound),
+                &http_request.get_version(),
+                http_request.get_encoding(),
             )
-            .optional_body(&file_content, is_not_head_request)
             .build()
+        }
+    };
+    response.add_error_body(
+        Some(http_request),
+        http_request.get_method() != HttpRequestMethod::HEAD,
+    );
+    let allowed_origin = http_request
+        .content()
+        .get_header("origin")
+        .and_then(|origin| config.allowed_origin(origin));
+    if let Some(allowed_origin) = allowed_origin {
+        if response
+            .content
+            .get_header("access-control-allow-origin")
+            .is_none()
+        {
+            response.add_header("access-control-allow-origin", allowed_origin);
+            response.add_vary("Origin");
+        }
+    }
+    response.set_server_header(config.server_header.as_deref());
+    if config.security_headers {
+        response.add_security_headers(&config.content_security_policy);
+    }
+    if let Some(content_language) = &config.content_language {
+        if response.content.get_header("content-language").is_none() {
+            response.add_header("content-language", content_language);
+        }
     }
+    response
+}
+
+// Copies the upload in chunks, a partially written file is removed. With `create_new`, an
+// existing file is left alone and `AlreadyExists` is returned
+fn write_upload(path: &Path, body: &mut dyn Read, create_new: bool) -> io::Result<()> {
+    let mut file = if create_new {
+        File::options().write(true).create_new(true).open(path)?
+    } else {
+        File::create(path)?
+    };
+    io::copy(body, &mut file).inspect_err(|_| {
+        let _ = fs::remove_file(path);
+    })?;
+    Ok(())
 }
 
-pub fn build_http_response(http_request: &HttpRequest) -> HttpResponse {
-    let resource = http_request
-        .get_url()
-        .resource()
-        .trim_start_matches(|c| c == '/' || c == '\\')
-        .to_string();
+// The client can do something about a missing directory or missing permissions,
+// everything else is on the server
+fn upload_error_code(err: &io::Error) -> ErrorCode {
+    match err.kind() {
+        io::ErrorKind::PermissionDenied => ErrorCode::Forbidden,
+        io::ErrorKind::NotFound | io::ErrorKind::NotADirectory => ErrorCode::NotFound,
+        _ => ErrorCode::InternalServerError,
+    }
+}
 
+fn build_resource_response(
+    http_request: &HttpRequest,
+    body: &mut dyn Read,
+    config: &ServerConfig,
+) -> HttpResponse {
     let version = http_request.get_version();
     let encoding = http_request.get_encoding();
-    let method = http_request.get_method();
-    let is_not_head_request = method != HttpRequestMethod::HEAD;
 
-    trace!(
-        "Method: {:?}, Resource: {}, Headers: {:?}",
+    let Some(decoded_resource) = http_request.get_url().decoded_resource() else {
+        return HttpResponseBuilder::new(
+            ResponseCode::Error(ErrorCode::BadRequest),
+            &version,
+            encoding,
+        )
+        .build();
+    };
+    let resource = decoded_resource.trim_start_matches(['/', '\\']).to_string();
+
+    let method = http_request.get_method();
+    let is_not_head_request = method != HttpRequestMethod::HEAD;
+
+    trace!(
+        "Method: {:?}, Resource: {}, Headers: {:?}",
         method,
         resource,
         http_request.content().get_headers()
@@ -270,12 +1044,33 @@ pub fn build_http_response(http_request: &HttpRequest) -> HttpResponse {
         HttpResponseBuilder::new(ResponseCode::Success(SuccessCode::Ok), &version, encoding);
     let not_found_response_builder =
         HttpResponseBuilder::new(ResponseCode::Error(ErrorCode::NotFound), &version, encoding);
+    let forbidden_response_builder = HttpResponseBuilder::new(
+        ResponseCode::Error(ErrorCode::Forbidden),
+        &version,
+        encoding,
+    );
     let internal_server_error_response_builder = HttpResponseBuilder::new(
         ResponseCode::Error(ErrorCode::InternalServerError),
         &version,
         encoding,
     );
 
+    // Methods the server doesn't implement at all are answered with 501 below
+    let method_name = format!("{:?}", method);
+    let allowed_methods = allowed_methods(&resource);
+    if HttpRequestMethod::supported_methods().contains(&method_name)
+        && !allowed_methods.contains(&method_name)
+    {
+        error!("Method {} is not allowed for {:?}", method_name, resource);
+        return HttpResponseBuilder::new(
+            ResponseCode::Error(ErrorCode::MethodNotAllowed),
+            &version,
+            encoding,
+        )
+        .header("allow", allowed_methods.join(", "))
+        .build();
+    }
+
     match method {
         HttpRequestMethod::GET | HttpRequestMethod::HEAD => match resource.as_str() {
             "" => ok_response_builder.build(),
@@ -290,40 +1085,159 @@ pub fn build_http_response(http_request: &HttpRequest) -> HttpResponse {
                 }
             }
             echo_request if resource.starts_with("echo/") => {
-                let echo = echo_request.strip_prefix("echo/").unwrap();
+                // The decoded path could carry line breaks, that must never reach the response
+                // framing, should the echo ever be reflected into a header
+                let echo: String = echo_request
+                    .strip_prefix("echo/")
+                    .unwrap()
+                    .chars()
+                    .filter(|c| !matches!(c, '\r' | '\n' | '\0'))
+                    .collect();
                 let echo_response = ok_response_builder
                     .header("content-type", "text/plain")
                     .optional_body(echo.as_bytes(), is_not_head_request)
                     .build();
 
-                return echo_response;
+                echo_response
             }
             _ => {
-                if let Some((auth_method, auth_data)) = http_request.auth_info() {
-                    let authenticated =
-                        Authenticator::authenticate(auth_data.as_bytes(), &auth_method);
-                    if !authenticated {
-                        return HttpResponseBuilder::new(
-                            ResponseCode::Error(ErrorCode::Unauthorized),
-                            &version,
-                            encoding,
-                        )
-                        .header("WWW-Authenticate", auth_method.to_string())
-                        .build();
+                if let Some(unauthorized_response) =
+                    check_credentials(http_request, config, &decoded_resource)
+                {
+                    return unauthorized_response;
+                }
+
+                if is_path_traversal(&resource) {
+                    error!("Path traversal attempt: {:?}", resource);
+                    return forbidden_response_builder.build();
+                }
+                if config.is_hidden(&resource) {
+                    error!("Hidden path: {:?}", resource);
+                    return forbidden_response_builder.build();
+                }
+                let Some(path) = config.resolve_path(&resource) else {
+                    error!("Path outside of the root: {:?}", resource);
+                    return forbidden_response_builder.build();
+                };
+
+                // Relative links in the directory index only work under `<dir>/`
+                if !decoded_resource.ends_with('/') && path.is_dir() {
+                    let url = http_request.get_url();
+                    let mut location = format!("{}/", url.resource());
+                    if !url.query().is_empty() {
+                        location = format!("{}?{}", location, url.query());
                     }
+                    return ok_response_builder.redirect(location, true).build();
                 }
 
-                let mb_file = fs::File::open(&resource);
-                let Ok(file) = mb_file else {
-                    error!(
-                        "Can't find `{:?}` error = {:?}",
-                        resource,
-                        mb_file.unwrap_err()
-                    );
-                    return not_found_response_builder.build();
+                let path = index_file_path(&path, &config.index_file).unwrap_or(path);
+                if config.is_forbidden_symlink(&path) {
+                    error!("Symlinks aren't followed: {:?}", resource);
+                    return forbidden_response_builder.build();
+                }
+                let file = match fs::File::open(&path) {
+                    Ok(file) => file,
+                    Err(err)
+                        if matches!(
+                            err.kind(),
+                            io::ErrorKind::NotFound | io::ErrorKind::NotADirectory
+                        ) =>
+                    {
+                        error!("Can't find `{:?}` error = {:?}", resource, err);
+                        return not_found_response_builder.build();
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+                        error!("Can't read `{:?}` error = {:?}", resource, err);
+                        return forbidden_response_builder.build();
+                    }
+                    Err(err) => {
+                        error!("Can't open `{:?}` error = {:?}", resource, err);
+                        return internal_server_error_response_builder.build();
+                    }
+                };
+
+                let metadata = file.metadata().ok();
+                if metadata.as_ref().is_some_and(fs::Metadata::is_dir) {
+                    if !config.directory_listing {
+                        error!("Directory listing is disabled: {:?}", resource);
+                        return not_found_response_builder.build();
+                    }
+                    return match directory_listing(&path, &resource) {
+                        Ok(listing) => ok_response_builder
+                            .header("content-type", "text/html")
+                            .optional_body(listing.as_bytes(), is_not_head_request)
+                            .build(),
+                        Err(err) => {
+                            error!("Failed to list directory {:?}: {:?}", resource, err);
+                            internal_server_error_response_builder.build()
+                        }
+                    };
+                }
+
+                let etag = metadata.as_ref().and_then(file_etag);
+                let last_modified = metadata.as_ref().and_then(last_modified);
+
+                let matched_etag = http_request
+                    .content()
+                    .get_header("if-none-match")
+                    .zip(etag.as_ref())
+                    .and_then(|(if_none_match, etag)| matching_etag(if_none_match, etag));
+                // `If-Modified-Since` is only considered without `If-None-Match`
+                let not_modified = match (
+                    http_request.content().get_header("if-none-match"),
+                    http_request.content().get_header("if-modified-since"),
+                ) {
+                    (Some(_), _) => matched_etag.is_some(),
+                    (None, Some(if_modified_since)) => {
+                        last_modified.as_ref().is_some_and(|last_modified| {
+                            !modified_since(if_modified_since, last_modified)
+                        })
+                    }
+                    (None, None) => false,
                 };
 
-                let Ok(content_type) = http_request.content().get_content_type(&resource) else {
+                // Changed resources are sent in full when `If-Range` doesn't match
+                let range_allowed =
+                    http_request
+                        .content()
+                        .get_header("if-range")
+                        .is_none_or(|if_range| {
+                            if_range_matches(if_range, etag.as_deref(), last_modified.as_ref())
+                        });
+
+                let mut validators = Vec::new();
+                // 304 carries the ETag of the representation the client has
+                if let Some(etag) = matched_etag.or(etag) {
+                    validators.push(("etag", etag));
+                }
+                if let Some(last_modified) = last_modified {
+                    validators.push((
+                        "last-modified",
+                        last_modified.format(HTTP_DATE_FORMAT).to_string(),
+                    ));
+                }
+
+                if not_modified {
+                    let not_modified_response_builder = HttpResponseBuilder::new(
+                        ResponseCode::Success(SuccessCode::NotModified),
+                        &version,
+                        None,
+                    );
+                    return with_max_age(
+                        validators
+                            .into_iter()
+                            .fold(not_modified_response_builder, |builder, (name, value)| {
+                                builder.header(name, value)
+                            }),
+                        config,
+                    )
+                    .build();
+                }
+
+                let Ok(content_type) = http_request
+                    .content()
+                    .get_content_type(&path.to_string_lossy(), &config.mime_types)
+                else {
                     error!("Unsupported media type: {}", resource);
                     return HttpResponseBuilder::new(
                         ResponseCode::Error(ErrorCode::UnsupportedMediaType),
@@ -333,58 +1247,301 @@ pub fn build_http_response(http_request: &HttpRequest) -> HttpResponse {
                     .build();
                 };
                 trace!("Content type: {}", content_type);
+                if !http_request.accepts(&content_type) {
+                    error!("{} isn't acceptable for the client", content_type);
+                    return HttpResponseBuilder::new(
+                        ResponseCode::Error(ErrorCode::NotAcceptable),
+                        &version,
+                        encoding,
+                    )
+                    .build();
+                }
 
                 // TODO: don't unwrap error, and don't use this pattern with mb_something then Ok()
-                let mb_file_content = read_file_content(&file, http_request.ranges());
-                let Ok(file_content) = mb_file_content else {
-                    return build_http_response_for_invalid_request(mb_file_content.unwrap_err());
-                };
+                let file_len = metadata.as_ref().map_or(0, fs::Metadata::len);
+                let ranges = http_request
+                    .ranges()
+                    .filter(|_| range_allowed)
+                    .map(|ranges| {
+                        if ranges.len() > MAX_RANGES {
+                            error!("Too many ranges requested: {}", ranges.len());
+                            Vec::new()
+                        } else {
+                            ranges.resolve(file_len)
+                        }
+                    });
+                if ranges.as_ref().is_some_and(Vec::is_empty) {
+                    return HttpResponseBuilder::new(
+                        ResponseCode::Error(ErrorCode::RangeNotSatisfiable),
+                        &version,
+                        encoding,
+                    )
+                    .header("content-range", format!("bytes */{}", file_len))
+                    .build();
+                }
+
+                let ok_response_builder = with_max_age(
+                    validators
+                        .into_iter()
+                        .fold(ok_response_builder, |builder, (name, value)| {
+                            builder.header(name, value)
+                        }),
+                    config,
+                )
+                .header("content-type", &content_type);
+
+                // Ranges refer to the uncompressed content, they are always cut from the file itself
+                let precompressed_file = ranges
+                    .is_none()
+                    .then(|| open_precompressed_file(&path, encoding, config))
+                    .flatten();
+                if let Some((file, file_len, encoding)) = precompressed_file {
+                    trace!("Serving precompressed {} file", encoding);
+                    let ok_response_builder = if !is_not_head_request {
+                        ok_response_builder.header("content-length", file_len.to_string())
+                    } else if file_len >= STREAMING_THRESHOLD {
+                        ok_response_builder.file_body(file, file_len)
+                    } else {
+                        let parts = [FilePart::Section {
+                            from: 0,
+                            len: file_len,
+                        }];
+                        match read_file_parts(&file, &parts) {
+                            Ok(file_content) => {
+                                ok_response_builder.precompressed_body(&file_content, encoding)
+                            }
+                            Err(err) => return build_http_response_for_invalid_request(err.into()),
+                        }
+                    };
+                    return ok_response_builder
+                        .header("content-encoding", encoding.to_string())
+                        .build();
+                }
+
+                // HEAD only needs the length of the file, the content isn't read. Like GET, files
+                // this large aren't compressed
+                if !is_not_head_request && ranges.is_none() && file_len >= STREAMING_THRESHOLD {
+                    return ok_response_builder
+                        .without_encoding()
+                        .header("content-length", file_len.to_string())
+                        .build();
+                }
+                if ranges.is_none() && file_len >= STREAMING_THRESHOLD {
+                    return ok_response_builder.file_body(file, file_len).build();
+                }
 
-                if let Some(ranges) = http_request.ranges() {
+                if let Some(ranges) = ranges {
                     return build_response_for_multipart_request(
-                        &http_request,
-                        &file_content,
+                        http_request,
+                        file,
                         &ranges,
                         &content_type,
+                        file_len,
                     );
                 }
 
-                ok_response_builder
-                    .header("content-type", content_type)
-                    .optional_body(&file_content, is_not_head_request)
-                    .build()
+                let parts = [FilePart::Section {
+                    from: 0,
+                    len: file_len,
+                }];
+                match read_file_parts(&file, &parts) {
+                    Ok(file_content) => ok_response_builder
+                        .optional_body(&file_content, is_not_head_request)
+                        .build(),
+                    Err(err) => build_http_response_for_invalid_request(err.into()),
+                }
             }
         },
         HttpRequestMethod::POST => {
-            let mb_file = fs::File::create(&resource);
-            let Ok(mut file) = mb_file else {
-                error!(
-                    "POST: Failed to create a file: {:?}. {:?}",
-                    &resource,
-                    mb_file.unwrap_err()
-                );
-                return internal_server_error_response_builder.build();
+            if let Some(unauthorized_response) =
+                check_credentials(http_request, config, &decoded_resource)
+            {
+                return unauthorized_response;
+            }
+            if is_path_traversal(&resource) {
+                error!("POST: Path traversal attempt: {:?}", resource);
+                return forbidden_response_builder.build();
+            }
+            if config.is_hidden(&resource) {
+                error!("POST: Hidden path: {:?}", resource);
+                return forbidden_response_builder.build();
+            }
+            let Some(path) = config.resolve_path(&resource) else {
+                error!("POST: Path outside of the root: {:?}", resource);
+                return forbidden_response_builder.build();
             };
 
-            let mb_success = file.write_all(&http_request.content().get_body());
-            let Ok(_) = mb_success else {
-                error!(
-                    "POST: Failed to write to file: {:?}. {:?}",
-                    &resource,
-                    mb_success.unwrap_err()
-                );
-                return internal_server_error_response_builder.build();
+            // Browser form uploads wrap the file into a multipart envelope
+            let multipart_form = match http_request
+                .content()
+                .get_header("content-type")
+                .and_then(|content_type| MultipartForm::boundary(content_type))
+            {
+                Some(boundary) => {
+                    match MultipartForm::parse(http_request.content().get_body(), &boundary) {
+                        Ok(form) => Some(form),
+                        Err(err) => return build_http_response_for_invalid_request(err),
+                    }
+                }
+                None => None,
+            };
+            let mut file_content: &mut dyn Read = match &multipart_form {
+                Some(form) => {
+                    let Some(file_part) = form.file_part() else {
+                        error!("POST: Multipart form without a file: {:?}", resource);
+                        return HttpResponseBuilder::new(
+                            ResponseCode::Error(ErrorCode::BadRequest),
+                            &version,
+                            encoding,
+                        )
+                        .build();
+                    };
+                    &mut file_part.get_body().as_slice()
+                }
+                None => body,
             };
 
-            return HttpResponseBuilder::new(
+            // POST only creates files, replacing them is left to PUT
+            match write_upload(&path, &mut file_content, true) {
+                Ok(_) => {}
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    error!("POST: File already exists: {:?}", resource);
+                    return HttpResponseBuilder::new(
+                        ResponseCode::Error(ErrorCode::Conflict),
+                        &version,
+                        encoding,
+                    )
+                    .build();
+                }
+                Err(err) => {
+                    error!("POST: Failed to write to file: {:?}. {:?}", &resource, err);
+                    return HttpResponseBuilder::new(
+                        ResponseCode::Error(upload_error_code(&err)),
+                        &version,
+                        encoding,
+                    )
+                    .build();
+                }
+            }
+
+            HttpResponseBuilder::new(
                 ResponseCode::Success(SuccessCode::Created),
                 &version,
                 encoding,
             )
-            .build();
+            .build()
+        }
+        HttpRequestMethod::PUT => {
+            if let Some(unauthorized_response) =
+                check_credentials(http_request, config, &decoded_resource)
+            {
+                return unauthorized_response;
+            }
+            if is_path_traversal(&resource) {
+                error!("PUT: Path traversal attempt: {:?}", resource);
+                return forbidden_response_builder.build();
+            }
+            if config.is_hidden(&resource) {
+                error!("PUT: Hidden path: {:?}", resource);
+                return forbidden_response_builder.build();
+            }
+            let Some(path) = config.resolve_path(&resource) else {
+                error!("PUT: Path outside of the root: {:?}", resource);
+                return forbidden_response_builder.build();
+            };
+
+            // Unlike POST, the whole resource is replaced, so repeating the request is harmless
+            let existed = path.is_file();
+            if let Err(err) = write_upload(&path, body, false) {
+                error!("PUT: Failed to write to file: {:?}. {:?}", &resource, err);
+                return HttpResponseBuilder::new(
+                    ResponseCode::Error(upload_error_code(&err)),
+                    &version,
+                    encoding,
+                )
+                .build();
+            }
+
+            if existed {
+                ok_response_builder.build()
+            } else {
+                HttpResponseBuilder::new(
+                    ResponseCode::Success(SuccessCode::Created),
+                    &version,
+                    encoding,
+                )
+                .build()
+            }
+        }
+        HttpRequestMethod::DELETE => {
+            if let Some(unauthorized_response) =
+                check_credentials(http_request, config, &decoded_resource)
+            {
+                return unauthorized_response;
+            }
+            if is_path_traversal(&resource) {
+                error!("DELETE: Path traversal attempt: {:?}", resource);
+                return forbidden_response_builder.build();
+            }
+            if config.is_hidden(&resource) {
+                error!("DELETE: Hidden path: {:?}", resource);
+                return forbidden_response_builder.build();
+            }
+            let Some(path) = config.resolve_path(&resource) else {
+                error!("DELETE: Path outside of the root: {:?}", resource);
+                return forbidden_response_builder.build();
+            };
+
+            match fs::remove_file(&path) {
+                Ok(_) => HttpResponseBuilder::new(
+                    ResponseCode::Success(SuccessCode::NoContent),
+                    &version,
+                    encoding,
+                )
+                .build(),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    not_found_response_builder.build()
+                }
+                Err(err) => {
+                    error!("DELETE: Failed to remove file: {:?}. {:?}", &resource, err);
+                    internal_server_error_response_builder.build()
+                }
+            }
         }
+        // Asterisk-form asks about the server rather than a resource
+        HttpRequestMethod::OPTIONS if decoded_resource == "*" => ok_response_builder
+            .header("allow", HttpRequestMethod::supported_methods().join(", "))
+            .build(),
         HttpRequestMethod::OPTIONS => {
-            let Ok(content_type) = http_request.content().get_content_type(&resource) else {
+            let preflight = (
+                http_request.content().get_header("origin"),
+                http_request
+                    .content()
+                    .get_header("access-control-request-method"),
+            );
+            if let (Some(origin), Some(_)) = preflight {
+                if let Some(allowed_origin) = config.allowed_origin(origin) {
+                    let allowed_headers = http_request
+                        .content()
+                        .get_header("access-control-request-headers")
+                        .map_or(CORS_ALLOWED_HEADERS, String::as_str);
+                    return HttpResponseBuilder::new(
+                        ResponseCode::Success(SuccessCode::NoContent),
+                        &version,
+                        encoding,
+                    )
+                    .header("access-control-allow-origin", allowed_origin)
+                    .header("access-control-allow-methods", allowed_methods.join(", "))
+                    .header("access-control-allow-headers", allowed_headers)
+                    .header("vary", "Origin")
+                    .build();
+                }
+            }
+
+            let Ok(content_type) = http_request
+                .content()
+                .get_content_type(&resource, &config.mime_types)
+            else {
                 error!("Unsupported media type: {}", resource);
                 return HttpResponseBuilder::new(
                     ResponseCode::Error(ErrorCode::UnsupportedMediaType),
@@ -395,11 +1552,17 @@ pub fn build_http_response(http_request: &HttpRequest) -> HttpResponse {
             };
 
             ok_response_builder
-                .header("allow", HttpRequestMethod::supported_methods().join(", "))
+                .header("allow", allowed_methods.join(", "))
                 .header("content-type", content_type)
                 .header("content-length", "0")
                 .build()
         }
+        // The request is never forwarded, so the server is always the final recipient, no matter
+        // what `Max-Forwards` says
+        HttpRequestMethod::TRACE => ok_response_builder
+            .header("content-type", "message/http")
+            .body(trace_body(http_request).as_bytes())
+            .build(),
         _ => HttpResponseBuilder::new(
             ResponseCode::Error(ErrorCode::NotImplemented),
             &version,
@@ -413,6 +1576,8 @@ pub fn build_http_response(http_request: &HttpRequest) -> HttpResponse {
 mod tests {
     use super::*;
 
+    use base64::prelude::*;
+    use flate2::{read::DeflateDecoder, write::GzEncoder, Compression};
     use std::{
         env::{current_dir, temp_dir},
         fs,
@@ -424,9 +1589,26 @@ mod tests {
         auth::{AuthMethod, Authenticator},
         common::{Range, Ranges, MAX_HEADER_SIZE, MAX_REQUEST_BODY_SIZE, MAX_URI_LENGTH},
         request::{parse_http_request, HttpRequestBuilder, HttpRequestLine, HttpRequestMethod},
+        testing::MockTcpStream,
         url::Url,
     };
 
+    // Resources are resolved relative to the working directory, which is the
+    // crate root when running `cargo test`
+    const TEST_FILE: &str = "src/main.rs";
+    const TEST_CREDENTIALS: &str = "admin:password";
+
+    fn authenticated_config() -> ServerConfig {
+        ServerConfig {
+            authenticator: Authenticator::new([TEST_CREDENTIALS]),
+            ..Default::default()
+        }
+    }
+
+    fn encoded_test_credentials() -> String {
+        BASE64_STANDARD.encode(TEST_CREDENTIALS)
+    }
+
     // UTILS
     fn generate_error_response_for(invalid_request: &str) -> HttpResponse {
         let mut stream = Cursor::new(invalid_request.as_bytes().to_vec());
@@ -441,14 +1623,51 @@ mod tests {
             .join(file_path)
     }
 
+    // Relative to the working directory, so it can be used as a request resource
+    fn get_tmp_file_path(file_name: &str) -> PathBuf {
+        let tmp_dir = PathBuf::from("target").join("tmp");
+        fs::create_dir_all(&tmp_dir).expect("Failed to create temporary directory");
+        tmp_dir.join(file_name)
+    }
+
     fn read_file(file_path: &PathBuf) -> Vec<u8> {
-        let mut file = fs::File::open(&file_path).expect("Can't open test file");
+        let mut file = fs::File::open(file_path).expect("Can't open test file");
         let mut file_content = Vec::new();
         file.read_to_end(&mut file_content)
             .expect("Failed to read test file");
         file_content
     }
 
+    // Splits a raw response into its head and its de-chunked body
+    fn decode_chunked_response(raw_response: &[u8]) -> (String, Vec<u8>) {
+        let head_end = raw_response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .expect("Response without headers")
+            + 4;
+        let head = String::from_utf8(raw_response[..head_end].to_vec()).unwrap();
+
+        let mut body = Vec::new();
+        let mut rest = &raw_response[head_end..];
+        loop {
+            let size_end = rest
+                .windows(2)
+                .position(|window| window == b"\r\n")
+                .unwrap();
+            let size_line = std::str::from_utf8(&rest[..size_end]).unwrap();
+            let size = usize::from_str_radix(size_line, 16).expect("Invalid chunk size");
+            rest = &rest[size_end + 2..];
+            if size == 0 {
+                assert_eq!(rest, b"\r\n");
+                break;
+            }
+            body.extend_from_slice(&rest[..size]);
+            assert_eq!(&rest[size..size + 2], b"\r\n");
+            rest = &rest[size + 2..];
+        }
+        (head, body)
+    }
+
     // BUILDERS
     fn request_get_builder(resource: &str) -> HttpRequestBuilder {
         HttpRequestBuilder::new(HttpRequestLine::new(
@@ -466,6 +1685,22 @@ mod tests {
         ))
     }
 
+    fn request_put_builder(resource: &str) -> HttpRequestBuilder {
+        HttpRequestBuilder::new(HttpRequestLine::new(
+            HttpRequestMethod::PUT,
+            Url::new(resource),
+            String::from("HTTP/1.1"),
+        ))
+    }
+
+    fn request_delete_builder(resource: &str) -> HttpRequestBuilder {
+        HttpRequestBuilder::new(HttpRequestLine::new(
+            HttpRequestMethod::DELETE,
+            Url::new(resource),
+            String::from("HTTP/1.1"),
+        ))
+    }
+
     fn request_head_builder(resource: &str) -> HttpRequestBuilder {
         HttpRequestBuilder::new(HttpRequestLine::new(
             HttpRequestMethod::HEAD,
@@ -486,20 +1721,40 @@ mod tests {
     #[test]
     fn response_get_empty() {
         let request = request_get_builder("/").build();
-        let response = build_http_response(&request);
+        let response = build_http_response(&request, &ServerConfig::default());
 
         assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
     }
 
+    #[test]
+    fn response_http_1_0() {
+        let request = HttpRequestBuilder::new(HttpRequestLine::new(
+            HttpRequestMethod::GET,
+            Url::new("/echo/test"),
+            String::from("1.0"),
+        ))
+        .build();
+        let response = build_http_response(&request, &ServerConfig::default());
+
+        assert!(response.as_bytes().starts_with(b"HTTP/1.0 200 OK\r\n"));
+
+        let response =
+            HttpResponseBuilder::new(ResponseCode::Success(SuccessCode::Ok), "1.0", None)
+                .streaming(true)
+                .body(b"test")
+                .build();
+        assert!(response.content.get_header("transfer-encoding").is_none());
+        assert_eq!(response.content.get_header("content-length").unwrap(), "4");
+    }
+
     #[test]
     fn response_get_user_agent() {
         let user_agent = "my-http-server";
         let request = request_get_builder("/user-agent")
             .header("user-agent", user_agent)
             .build();
-        let response = build_http_response(&request);
+        let response = build_http_response(&request, &ServerConfig::default());
 
-        let response = response;
         assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
         assert!(response
             .content
@@ -510,287 +1765,2057 @@ mod tests {
     #[test]
     fn response_get_echo() {
         let request = request_get_builder("/echo/test").build();
-        let response = build_http_response(&request);
+        let response = build_http_response(&request, &ServerConfig::default());
 
         assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
         assert!(response.content.get_body().starts_with(b"test"));
     }
 
     #[test]
-    fn response_get_file() {
-        let file_full_path = get_full_path("src/main.rs");
-        let file_content = read_file(&file_full_path);
-
-        let request = request_get_builder(&file_full_path.display().to_string()).build();
-        let response = build_http_response(&request);
+    fn response_get_echo_control_characters() {
+        let request = request_get_builder("/echo/a%0d%0aX-Injected:%20yes%00%0A").build();
+        let response = build_http_response(&request, &ServerConfig::default());
 
         assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
-        assert_eq!(
-            response.content.get_header("content-type").unwrap(),
-            "text/x-rust"
-        );
-        assert!(response.content.get_body().starts_with(&file_content));
+        assert_eq!(response.content.get_body(), b"aX-Injected: yes");
+        assert!(response.content.get_header("x-injected").is_none());
+
+        let raw_response = response.as_bytes();
+        let raw_response = String::from_utf8_lossy(&raw_response);
+        let (head, body) = raw_response.split_once("\r\n\r\n").unwrap();
+        assert!(!head.to_ascii_lowercase().contains("x-injected"));
+        assert_eq!(body, "aX-Injected: yes");
     }
 
     #[test]
-    fn response_get_partial_content_single_range() {
-        let file_full_path = get_full_path("src/main.rs");
-        let file_content = read_file(&file_full_path);
-        let range = Range::new(0, 64);
-        let ranges = Ranges::new(vec![range.clone()]);
-        let request = request_get_builder(&file_full_path.display().to_string())
-            .set_range(ranges.clone())
+    fn response_get_echo_deflate() {
+        let echo = "test".repeat(MIN_COMPRESSION_SIZE);
+        let request = request_get_builder(&format!("/echo/{}", echo))
+            .set_encoding(ContentEncoding::Deflate)
             .build();
-        let response = build_http_response(&request);
+        let response = build_http_response(&request, &ServerConfig::default());
 
         assert_eq!(
-            response.status_code,
-            ResponseCode::Success(SuccessCode::PartialContent)
-        );
-        assert_eq!(
-            response.content.get_header("content-range").unwrap(),
-            format!("bytes {}-{}", range.from, range.to).as_str()
-        );
-        assert_eq!(
-            response.content.get_body().len(),
-            (range.to - range.from) as usize
+            response.content.get_header("content-encoding").unwrap(),
+            "deflate"
         );
-        let partial_file_content =
-            &file_content[(range.from as usize)..(range.to as usize)].to_vec();
-        assert_eq!(response.content.get_body(), partial_file_content);
+        let mut decompressed = Vec::new();
+        DeflateDecoder::new(response.content.get_body().as_slice())
+            .read_to_end(&mut decompressed)
+            .expect("Failed to decompress deflate body");
+        assert_eq!(decompressed, echo.as_bytes());
     }
 
     #[test]
-    fn response_get_partial_content_multiple_ranges() {
-        let file_full_path = get_full_path("src/main.rs");
-        let range = Range::new(0, 64);
-        let ranges = Ranges::new(vec![range.clone(), range.clone()]);
-        let request = request_get_builder(&file_full_path.display().to_string())
-            .set_range(ranges.clone())
+    fn response_get_echo_below_compression_threshold() {
+        let request = request_get_builder("/echo/hello")
+            .set_encoding(ContentEncoding::Gzip)
             .build();
-        let response = build_http_response(&request);
+        let response = build_http_response(&request, &ServerConfig::default());
 
-        assert_eq!(
-            response.status_code,
-            ResponseCode::Success(SuccessCode::PartialContent)
-        );
+        assert!(response.content.get_header("content-encoding").is_none());
+        assert_eq!(response.content.get_body(), b"hello");
+    }
 
-        fn count(s: &str, response_body: &String) -> usize {
-            response_body.match_indices(s).collect::<Vec<_>>().len()
-        }
+    #[test]
+    fn response_get_percent_encoded_file() {
+        let tmp_file_path = get_tmp_file_path("percent encoded+file.txt");
+        fs::write(&tmp_file_path, b"percent encoded").unwrap();
 
-        let response_body = String::from_utf8(response.content().get_body().clone())
-            .expect("Failed to convert body to string");
-        let number_of_ranges = ranges.len();
+        let request = request_get_builder("/target/tmp/percent%20encoded%2Bfile.txt").build();
+        let response = build_http_response(&request, &ServerConfig::default());
 
-        assert_eq!(
-            count(HttpResponse::partial_content_boundary(), &response_body),
-            number_of_ranges
-        );
-        assert_eq!(count("content-type", &response_body), number_of_ranges);
-        assert_eq!(count("content-range", &response_body), number_of_ranges);
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+        assert_eq!(response.content.get_body(), b"percent encoded");
     }
 
     #[test]
-    fn response_get_file_not_found() {
-        let request = request_get_builder("/nonexistent_file").build();
-        let response = build_http_response(&request);
+    fn response_path_traversal() {
+        let tmp_file_path = get_tmp_file_path("sub").join("ok.txt");
+        fs::create_dir_all(tmp_file_path.parent().unwrap()).unwrap();
+        fs::write(&tmp_file_path, b"ok").unwrap();
+
+        for resource in ["/target/tmp/sub/ok.txt", "/target//tmp/./sub//ok.txt"] {
+            let request = request_get_builder(resource).build();
+            let response = build_http_response(&request, &ServerConfig::default());
+            assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+        }
+
+        for resource in [
+            "/target/tmp/../secret",
+            "/target/tmp/sub/../../escape",
+            "/target/tmp/sub/%2E%2E/%2e%2e/escape",
+            "/target/tmp/sub/..%2F..%2Fescape",
+            "/target/tmp/sub/..\\escape",
+            "/target/tmp/sub/./..//../escape",
+        ] {
+            let request = request_get_builder(resource).build();
+            let response = build_http_response(&request, &ServerConfig::default());
+            assert_eq!(
+                response.status_code,
+                ResponseCode::Error(ErrorCode::Forbidden),
+                "{}",
+                resource
+            );
+        }
 
+        let request = request_post_builder("/target/tmp/../escape").build();
+        let response = build_http_response(&request, &ServerConfig::default());
         assert_eq!(
             response.status_code,
-            ResponseCode::Error(ErrorCode::NotFound)
+            ResponseCode::Error(ErrorCode::Forbidden)
         );
     }
 
     #[test]
-    fn response_unauthorized_request() {
-        let request = request_get_builder("/test")
-            .set_auth_info((AuthMethod::Basic, String::from("djkfdskjf")))
-            .build();
-        let response = build_http_response(&request);
+    fn response_get_if_none_match() {
+        let request = request_get_builder(TEST_FILE).build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+        let etag = response.content.get_header("etag").unwrap().clone();
 
+        let request = request_get_builder(TEST_FILE)
+            .header("if-none-match", &etag)
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
         assert_eq!(
             response.status_code,
-            ResponseCode::Error(ErrorCode::Unauthorized)
+            ResponseCode::Success(SuccessCode::NotModified)
         );
-    }
+        assert_eq!(response.content.get_header("etag").unwrap(), &etag);
+        assert!(response.content.get_header("content-length").is_none());
+        assert!(response.content.get_body().is_empty());
 
-    #[test]
-    fn response_authorized_request() {
-        let request = request_get_builder("/test")
-            .header(
-                "authorization",
-                format!("Basic {}", Authenticator::default_credentials()),
-            )
+        let request = request_get_builder(TEST_FILE)
+            .header("if-none-match", format!("\"other\", W/{}", etag))
             .build();
-        let response = build_http_response(&request);
-
-        assert_ne!(
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(
             response.status_code,
-            ResponseCode::Error(ErrorCode::Unauthorized)
+            ResponseCode::Success(SuccessCode::NotModified)
         );
+
+        let request = request_get_builder(TEST_FILE)
+            .header("if-none-match", "\"other\"")
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
     }
 
     #[test]
-    fn response_with_invalid_request_bad_request() {
-        let invalid_requests = [
-            String::from(""),
-            String::from("GET /"),
-            String::from("GET / HTTP/1.1\r\nWrongHeader=value"),
-        ];
+    fn response_get_if_modified_since() {
+        let tmp_file_path = get_tmp_file_path("test_last_modified.txt");
+        let file = fs::File::create(&tmp_file_path).unwrap();
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        let modified = UNIX_EPOCH + std::time::Duration::from_secs(784111777);
+        file.set_modified(modified).unwrap();
+        let resource = tmp_file_path.display().to_string();
+
+        let request = request_get_builder(&resource).build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(
+            response.content.get_header("last-modified").unwrap(),
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
 
-        for invalid_request in invalid_requests {
-            let error_response = generate_error_response_for(&invalid_request);
-            assert_eq!(
-                error_response.status_code,
-                ResponseCode::Error(ErrorCode::BadRequest)
+        let not_modified_since = [
+            Utc::now() + chrono::Duration::days(1),
+            DateTime::from(modified),
+        ];
+        for since in not_modified_since {
+            let request = request_get_builder(&resource)
+                .header(
+                    "if-modified-since",
+                    since.format(HTTP_DATE_FORMAT).to_string(),
+                )
+                .build();
+            let response = build_http_response(&request, &ServerConfig::default());
+            assert_eq!(
+                response.status_code,
+                ResponseCode::Success(SuccessCode::NotModified)
             );
+            assert!(response.content.get_body().is_empty());
+        }
+
+        for since in ["Sat, 05 Nov 1994 08:49:37 GMT", "not a date"] {
+            let request = request_get_builder(&resource)
+                .header("if-modified-since", since)
+                .build();
+            let response = build_http_response(&request, &ServerConfig::default());
+            assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
         }
     }
 
     #[test]
-    fn response_with_invalid_request_internal_server_error() {
-        let invalid_requests = [String::from(
-            "GET / HTTP/1.1\r\nContent-Length : -32\r\n\r\n",
-        )];
+    fn response_get_max_age() {
+        let request = request_get_builder(TEST_FILE).build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert!(response.content.get_header("cache-control").is_none());
+        assert!(response.content.get_header("expires").is_none());
+
+        let config = ServerConfig {
+            max_age: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        let response = build_http_response(&request, &config);
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+        assert_eq!(
+            response.content.get_header("cache-control").unwrap(),
+            "max-age=3600"
+        );
+        let parse_date = |name: &str| {
+            let date = response.content.get_header(name).unwrap();
+            NaiveDateTime::parse_from_str(date, HTTP_DATE_FORMAT).unwrap()
+        };
+        assert_eq!(
+            parse_date("expires") - parse_date("date"),
+            TimeDelta::seconds(3600)
+        );
 
-        for invalid_request in invalid_requests {
-            let error_response = generate_error_response_for(&invalid_request);
+        // Revalidated responses get them as well
+        let etag = response.content.get_header("etag").unwrap();
+        let request = request_get_builder(TEST_FILE)
+            .header("if-none-match", etag)
+            .build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Success(SuccessCode::NotModified)
+        );
+        assert_eq!(
+            response.content.get_header("cache-control").unwrap(),
+            "max-age=3600"
+        );
+        assert!(response.content.get_header("expires").is_some());
+    }
+
+    #[test]
+    fn response_get_if_range() {
+        let tmp_file_path = get_tmp_file_path("test_if_range.txt");
+        fs::write(&tmp_file_path, "0123456789").unwrap();
+        // Sun, 06 Nov 1994 08:49:37 GMT
+        let modified = UNIX_EPOCH + std::time::Duration::from_secs(784111777);
+        fs::File::options()
+            .write(true)
+            .open(&tmp_file_path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let resource = tmp_file_path.display().to_string();
+
+        let response = build_http_response(
+            &request_get_builder(&resource).build(),
+            &ServerConfig::default(),
+        );
+        let etag = response.content.get_header("etag").unwrap().clone();
+        let ranges = Ranges::new(vec![Range::new(2, 4).into()]);
+
+        for if_range in [etag.as_str(), "Sun, 06 Nov 1994 08:49:37 GMT"] {
+            let request = request_get_builder(&resource)
+                .set_range(ranges.clone())
+                .header("if-range", if_range)
+                .build();
+            let response = build_http_response(&request, &ServerConfig::default());
             assert_eq!(
-                error_response.status_code,
-                ResponseCode::Error(ErrorCode::InternalServerError)
+                response.status_code,
+                ResponseCode::Success(SuccessCode::PartialContent)
             );
+            assert_eq!(response.content.get_body(), b"234");
+        }
+
+        let stale_validators = [
+            String::from("\"other\""),
+            format!("W/{}", etag),
+            String::from("Sat, 05 Nov 1994 08:49:37 GMT"),
+            String::from("not a date"),
+        ];
+        for if_range in stale_validators {
+            let request = request_get_builder(&resource)
+                .set_range(ranges.clone())
+                .header("if-range", if_range)
+                .build();
+            let response = build_http_response(&request, &ServerConfig::default());
+            assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+            assert!(response.content.get_header("content-range").is_none());
+            assert_eq!(response.content.get_body(), b"0123456789");
         }
     }
 
     #[test]
-    fn response_with_invalid_request_content_too_large() {
-        let invalid_request = format!(
-            "GET / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
-            MAX_REQUEST_BODY_SIZE + 1
+    fn response_get_file_above_compression_threshold() {
+        let file_content = read_file(&get_full_path(TEST_FILE));
+        assert!(file_content.len() >= MIN_COMPRESSION_SIZE);
+
+        let request = request_get_builder(TEST_FILE)
+            .set_encoding(ContentEncoding::Gzip)
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
+
+        assert_eq!(
+            response.content.get_header("content-encoding").unwrap(),
+            "gzip"
         );
-        let error_response = generate_error_response_for(&invalid_request);
+        let decompressed =
+            Compressor::decompress(response.content.get_body(), ContentEncoding::Gzip).unwrap();
+        assert_eq!(decompressed, file_content);
+    }
 
+    #[test]
+    fn response_vary_accept_encoding() {
+        let request = request_get_builder(TEST_FILE)
+            .set_encoding(ContentEncoding::Gzip)
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
         assert_eq!(
-            error_response.status_code,
-            ResponseCode::Error(ErrorCode::ContentTooLarge)
+            response.content.get_header("content-encoding").unwrap(),
+            "gzip"
+        );
+        assert_eq!(
+            response.content.get_header("vary").unwrap(),
+            "Accept-Encoding"
         );
+
+        let request = request_get_builder(TEST_FILE).build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert!(response.content.get_header("content-encoding").is_none());
+        assert!(response.content.get_header("vary").is_none());
     }
 
     #[test]
-    fn response_with_invalid_request_not_accepted() {
-        let not_supported_encoding = ContentEncoding::Pack200gzip.to_string();
-        let invalid_request = format!(
-            "GET /echo/test HTTP/1.1\r\nAccept-Encoding : {}",
-            not_supported_encoding
+    fn response_server_header() {
+        let request = request_get_builder(TEST_FILE).build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(
+            response.content.get_header("server").unwrap(),
+            DEFAULT_SERVER_HEADER
         );
-        let error_response = generate_error_response_for(&invalid_request);
 
+        let config = ServerConfig {
+            server_header: Some(String::from("custom/1.0")),
+            ..Default::default()
+        };
+        let response = build_http_response(&request, &config);
+        assert_eq!(response.content.get_header("server").unwrap(), "custom/1.0");
+
+        let config = ServerConfig {
+            server_header: None,
+            ..Default::default()
+        };
+        let response = build_http_response(&request, &config);
+        assert!(response.content.get_header("server").is_none());
+    }
+
+    #[test]
+    fn response_content_language() {
+        let request = request_get_builder(TEST_FILE).build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert!(response.content.get_header("content-language").is_none());
+
+        let config = ServerConfig {
+            content_language: Some(String::from("en-US")),
+            ..Default::default()
+        };
+        let response = build_http_response(&request, &config);
         assert_eq!(
-            error_response.status_code,
-            ResponseCode::Error(ErrorCode::NotAcceptable)
+            response.content.get_header("content-language").unwrap(),
+            "en-US"
         );
     }
 
     #[test]
-    fn response_with_invalid_request_uri_too_long() {
-        let invalid_request = format!(
-            "GET {} HTTP/1.1\r\n",
-            ["X"; (MAX_URI_LENGTH as usize) + 2].concat()
+    fn response_custom_compression_threshold() {
+        let response = HttpResponseBuilder::new(
+            ResponseCode::Success(SuccessCode::Ok),
+            DEFAULT_HTTP_VERSION,
+            Some(ContentEncoding::Gzip),
+        )
+        .min_compression_size(0)
+        .body(b"hello")
+        .build();
+
+        assert_eq!(
+            response.content.get_header("content-encoding").unwrap(),
+            "gzip"
         );
-        let error_response = generate_error_response_for(&invalid_request);
+    }
+
+    #[test]
+    fn response_get_file() {
+        let file_full_path = get_full_path(TEST_FILE);
+        let file_content = read_file(&file_full_path);
 
+        let request = request_get_builder(TEST_FILE).build();
+        let response = build_http_response(&request, &ServerConfig::default());
+
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
         assert_eq!(
-            error_response.status_code,
-            ResponseCode::Error(ErrorCode::URITooLong)
+            response.content.get_header("content-type").unwrap(),
+            "text/x-rust"
         );
+        assert!(response.content.get_body().starts_with(&file_content));
     }
 
     #[test]
-    fn response_with_invalid_request_header_too_large() {
-        let invalid_request = format!(
-            "GET / HTTP/1.1\r\ntest:{}",
-            ["X"; MAX_HEADER_SIZE as usize].concat()
+    fn response_get_file_not_acceptable() {
+        for (accept, status_code) in [
+            ("text/plain", ResponseCode::Error(ErrorCode::NotAcceptable)),
+            ("image/png", ResponseCode::Error(ErrorCode::NotAcceptable)),
+            ("text/*", ResponseCode::Success(SuccessCode::Ok)),
+            (
+                "text/x-rust, */*;q=0",
+                ResponseCode::Success(SuccessCode::Ok),
+            ),
+        ] {
+            let request = request_get_builder(TEST_FILE)
+                .header("Accept", accept)
+                .build();
+            let response = build_http_response(&request, &ServerConfig::default());
+            assert_eq!(response.status_code, status_code, "{}", accept);
+        }
+    }
+
+    #[test]
+    fn response_get_precompressed_file() {
+        let file_path = get_tmp_file_path("precompressed.txt");
+        let file_content = "plain text ".repeat(100);
+        fs::write(&file_path, &file_content).unwrap();
+
+        let mut gzip_encoder = GzEncoder::new(Vec::new(), Compression::best());
+        gzip_encoder.write_all(file_content.as_bytes()).unwrap();
+        let gzip_content = gzip_encoder.finish().unwrap();
+        fs::write(get_tmp_file_path("precompressed.txt.gz"), &gzip_content).unwrap();
+        let brotli_content = b"not really brotli".to_vec();
+        fs::write(get_tmp_file_path("precompressed.txt.br"), &brotli_content).unwrap();
+
+        let request = request_get_builder(&file_path.display().to_string()).build();
+        let identity_etag = build_http_response(&request, &ServerConfig::default())
+            .content
+            .get_header("etag")
+            .unwrap()
+            .clone();
+
+        for (encoding, expected_body) in [
+            (ContentEncoding::Gzip, &gzip_content),
+            (ContentEncoding::Br, &brotli_content),
+        ] {
+            let request = request_get_builder(&file_path.display().to_string())
+                .set_encoding(encoding)
+                .build();
+            let response = build_http_response(&request, &ServerConfig::default());
+
+            assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+            assert_eq!(
+                response.content.get_header("content-encoding").unwrap(),
+                &encoding.to_string()
+            );
+            assert_eq!(
+                response.content.get_header("content-type").unwrap(),
+                "text/plain"
+            );
+            assert_eq!(response.content.get_body(), expected_body);
+            let etag = response.content.get_header("etag").unwrap().clone();
+            assert_eq!(etag, encoded_etag(&identity_etag, &encoding.to_string()));
+
+            // The client revalidates the representation it has
+            let request = request_get_builder(&file_path.display().to_string())
+                .set_encoding(encoding)
+                .header("if-none-match", &etag)
+                .build();
+            let response = build_http_response(&request, &ServerConfig::default());
+            assert_eq!(
+                response.status_code,
+                ResponseCode::Success(SuccessCode::NotModified)
+            );
+            assert_eq!(response.content.get_header("etag").unwrap(), &etag);
+        }
+
+        // Compressed on the fly without a precompressed file
+        let request = request_get_builder(&file_path.display().to_string())
+            .set_encoding(ContentEncoding::Deflate)
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(
+            response.content.get_header("content-encoding").unwrap(),
+            "deflate"
         );
-        let error_response = generate_error_response_for(&invalid_request);
+        assert_eq!(
+            response.content.get_header("etag").unwrap(),
+            &format!("{}-deflate\"", identity_etag.trim_end_matches('"'))
+        );
+        let mut decompressed = Vec::new();
+        DeflateDecoder::new(response.content.get_body().as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, file_content.as_bytes());
+    }
+
+    #[test]
+    fn response_get_file_mime_type_override() {
+        let wasm_file_path = get_tmp_file_path("module.WASM");
+        fs::write(&wasm_file_path, b"\0asm").unwrap();
+        let config = ServerConfig {
+            mime_types: HashMap::from([
+                (String::from("wasm"), String::from("application/wasm")),
+                (String::from("rs"), String::from("text/plain")),
+            ]),
+            ..Default::default()
+        };
 
+        let request = request_get_builder(&wasm_file_path.display().to_string()).build();
+        let response = build_http_response(&request, &config);
         assert_eq!(
-            error_response.status_code,
-            ResponseCode::Error(ErrorCode::RequestHeaderFieldsTooLarge)
+            response.content.get_header("content-type").unwrap(),
+            "application/wasm"
+        );
+
+        // Overrides win over the guessed type
+        let request = request_get_builder(TEST_FILE).build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(
+            response.content.get_header("content-type").unwrap(),
+            "text/plain"
+        );
+
+        // Unmapped extensions fall back to the guessed type
+        let png_file_path = get_tmp_file_path("mime_fallback.png");
+        fs::write(&png_file_path, b"\x89PNG").unwrap();
+        let request = request_get_builder(&png_file_path.display().to_string()).build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(
+            response.content.get_header("content-type").unwrap(),
+            "image/png"
         );
     }
 
     #[test]
-    fn response_with_invalid_request_http_version_not_supported() {
-        let invalid_request = "GET / HTTP/3.0\r\n";
-        let error_response = generate_error_response_for(&invalid_request);
+    fn response_get_incompressible_file() {
+        let png_file_path = get_tmp_file_path("test.png");
+        let png_content = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+        fs::write(&png_file_path, &png_content).expect("Failed to create test file");
+
+        let request = request_get_builder(&png_file_path.display().to_string())
+            .set_encoding(ContentEncoding::Gzip)
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
+
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+        assert!(response.content.get_header("content-encoding").is_none());
+        assert_eq!(response.content.get_body(), &png_content);
+    }
+
+    #[test]
+    fn response_get_partial_content_single_range() {
+        let file_full_path = get_full_path(TEST_FILE);
+        let file_content = read_file(&file_full_path);
+        let range = Range::new(0, 64);
+        let ranges = Ranges::new(vec![range.clone().into()]);
+        let request = request_get_builder(TEST_FILE)
+            .set_range(ranges.clone())
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
 
         assert_eq!(
-            error_response.status_code,
-            ResponseCode::Error(ErrorCode::HTTPVersionNotSupported)
+            response.status_code,
+            ResponseCode::Success(SuccessCode::PartialContent)
+        );
+        assert_eq!(
+            response.content.get_header("content-range").unwrap(),
+            format!("bytes {}-{}/{}", range.from, range.to, file_content.len()).as_str()
         );
+        assert_eq!(response.content.get_body().len(), 65);
+        let partial_file_content =
+            &file_content[(range.from as usize)..=(range.to as usize)].to_vec();
+        assert_eq!(response.content.get_body(), partial_file_content);
     }
 
-    // POST REQUEST TESTS
     #[test]
-    fn response_post() {
-        let tmp_file_path = temp_dir().join("test.txt");
-        let file_data = b"data for testing POST request".to_vec();
+    fn response_get_partial_content_suffix_range() {
+        let file_content = read_file(&get_full_path(TEST_FILE));
+        assert!(file_content.len() > 500);
 
-        let request = request_post_builder(&tmp_file_path.display().to_string())
-            .body(&file_data)
+        let request = request_get_builder(TEST_FILE)
+            .set_range("bytes=-500".parse().unwrap())
             .build();
-        let response = build_http_response(&request);
+        let response = build_http_response(&request, &ServerConfig::default());
 
-        let mut file = fs::File::open(&tmp_file_path).expect("POST request failed to create file");
-        let mut file_content_create_by_post_request = Vec::new();
-        file.read_to_end(&mut file_content_create_by_post_request)
-            .expect("Failed to read test file");
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Success(SuccessCode::PartialContent)
+        );
+        assert_eq!(
+            response.content.get_body(),
+            &file_content[file_content.len() - 500..]
+        );
+
+        // Longer than the file, the whole content is sent
+        let request = request_get_builder(TEST_FILE)
+            .set_range(
+                format!("bytes=-{}", file_content.len() * 2)
+                    .parse()
+                    .unwrap(),
+            )
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(response.content.get_body(), &file_content);
+    }
+
+    #[test]
+    fn response_get_partial_content_open_ended_range() {
+        let file_content = read_file(&get_full_path(TEST_FILE));
+        assert!(file_content.len() > 500);
+
+        let request = request_get_builder(TEST_FILE)
+            .set_range("bytes=500-".parse().unwrap())
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
 
         assert_eq!(
             response.status_code,
-            ResponseCode::Success(SuccessCode::Created)
+            ResponseCode::Success(SuccessCode::PartialContent)
         );
-        assert_eq!(file_content_create_by_post_request, file_data);
+        assert_eq!(
+            response.content.get_header("content-range").unwrap(),
+            &format!(
+                "bytes 500-{}/{}",
+                file_content.len() - 1,
+                file_content.len()
+            )
+        );
+        assert_eq!(response.content.get_body(), &file_content[500..]);
     }
 
     #[test]
-    fn response_post_invalid() {
-        let request = request_post_builder("/nonexistent/test").build();
-        let response = build_http_response(&request);
+    fn response_get_range_not_satisfiable() {
+        let file_content = read_file(&get_full_path(TEST_FILE));
+        let file_len = file_content.len() as u64;
+
+        let request = request_get_builder(TEST_FILE)
+            .set_range(Ranges::new(vec![Range::new(999999, 1000000).into()]))
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Error(ErrorCode::RangeNotSatisfiable)
+        );
+        assert_eq!(
+            response.content.get_header("content-range").unwrap(),
+            &format!("bytes */{}", file_len)
+        );
+        assert_eq!(response.content.get_body(), b"416 Range Not Satisfiable");
+
+        // Partially overlapping range is clamped to the end of the file
+        let request = request_get_builder(TEST_FILE)
+            .set_range(Ranges::new(vec![
+                Range::new(file_len - 10, file_len + 100).into()
+            ]))
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Success(SuccessCode::PartialContent)
+        );
+        assert_eq!(
+            response.content.get_body(),
+            &file_content[file_content.len() - 10..]
+        );
+    }
+
+    #[test]
+    fn response_get_partial_content_multiple_ranges() {
+        let ranges = Ranges::new(vec![Range::new(100, 164).into(), Range::new(0, 64).into()]);
+        let request = request_get_builder(TEST_FILE)
+            .set_range(ranges.clone())
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
 
         assert_eq!(
             response.status_code,
-            ResponseCode::Error(ErrorCode::InternalServerError)
+            ResponseCode::Success(SuccessCode::PartialContent)
+        );
+
+        fn count(s: &str, response_body: &str) -> usize {
+            response_body.match_indices(s).collect::<Vec<_>>().len()
+        }
+
+        let response_body = String::from_utf8(response.content().get_body().clone())
+            .expect("Failed to convert body to string");
+        let number_of_ranges = ranges.len();
+
+        assert_eq!(
+            count(HttpResponse::partial_content_boundary(), &response_body),
+            number_of_ranges
         );
+        assert_eq!(count("content-type", &response_body), number_of_ranges);
+        assert_eq!(count("content-range", &response_body), number_of_ranges);
+        let file_len = read_file(&get_full_path(TEST_FILE)).len();
+        // Parts are sorted by their position in the file
+        let first_part = response_body
+            .find(&format!("content-range: bytes 0-64/{}\r\n", file_len))
+            .unwrap();
+        let second_part = response_body
+            .find(&format!("content-range: bytes 100-164/{}\r\n", file_len))
+            .unwrap();
+        assert!(first_part < second_part);
     }
 
-    // HEAD requests
     #[test]
-    fn response_head_file() {
-        let file_full_path = get_full_path("src/main.rs");
+    fn response_get_overlapping_ranges() {
+        let file_content = read_file(&get_full_path(TEST_FILE));
+        let file_len = file_content.len();
+        let request = request_get_builder(TEST_FILE)
+            .set_range("bytes=50-99, 0-59, 100-120, 10-20".parse().unwrap())
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
+
+        // A single range is left, it's sent without the multipart envelope
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Success(SuccessCode::PartialContent)
+        );
+        assert_eq!(
+            response.content.get_header("content-range").unwrap(),
+            &format!("bytes 0-120/{}", file_len)
+        );
+        assert_eq!(response.content.get_body(), &file_content[..=120]);
+    }
 
-        let request = request_head_builder(&file_full_path.display().to_string()).build();
-        let response = build_http_response(&request);
+    #[test]
+    fn response_get_too_many_ranges() {
+        let ranges = (0..=MAX_RANGES)
+            .map(|i| format!("{}-{}", i * 2, i * 2))
+            .collect::<Vec<_>>()
+            .join(",");
+        let request = request_get_builder(TEST_FILE)
+            .set_range(format!("bytes={}", ranges).parse().unwrap())
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
 
-        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
         assert_eq!(
-            response.content.get_header("content-type").unwrap(),
-            "text/x-rust"
+            response.status_code,
+            ResponseCode::Error(ErrorCode::RangeNotSatisfiable)
         );
+    }
 
-        assert!(response.content.get_body().is_empty());
+    #[test]
+    fn response_get_file_not_found() {
+        let request = request_get_builder("/nonexistent_file").build();
+        let response = build_http_response(&request, &ServerConfig::default());
+
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Error(ErrorCode::NotFound)
+        );
     }
 
-    // OPTIONS requests
+    #[cfg(unix)]
     #[test]
-    fn response_options() {
-        let file_full_path = get_full_path("src/main.rs");
-        let options_request =
-            request_options_builder(&file_full_path.display().to_string()).build();
-        let response = build_http_response(&options_request);
+    fn response_get_file_permission_denied() {
+        use std::os::unix::fs::PermissionsExt;
 
-        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
-        assert!(response.content().get_header("allow").is_some());
+        let tmp_file_path = get_tmp_file_path("permission_denied.txt");
+        fs::write(&tmp_file_path, b"secret").unwrap();
+        fs::set_permissions(&tmp_file_path, fs::Permissions::from_mode(0o000)).unwrap();
+
+        // Root can read the file regardless of its permissions
+        if fs::File::open(&tmp_file_path).is_err() {
+            let request = request_get_builder(&format!("/{}", tmp_file_path.display())).build();
+            let response = build_http_response(&request, &ServerConfig::default());
+            assert_eq!(
+                response.status_code,
+                ResponseCode::Error(ErrorCode::Forbidden)
+            );
+        }
+        fs::set_permissions(&tmp_file_path, fs::Permissions::from_mode(0o644)).unwrap();
+    }
+
+    #[test]
+    fn response_upload_missing_directory() {
+        let tmp_file_path = get_tmp_file_path("missing_directory").join("upload.txt");
+        let _ = fs::remove_dir_all(tmp_file_path.parent().unwrap());
+        let resource = format!("/{}", tmp_file_path.display());
+
+        for request in [
+            request_post_builder(&resource).body(b"data").build(),
+            request_put_builder(&resource).body(b"data").build(),
+        ] {
+            let response = build_http_response(&request, &ServerConfig::default());
+            assert_eq!(
+                response.status_code,
+                ResponseCode::Error(ErrorCode::NotFound)
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn response_upload_permission_denied() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let directory = get_tmp_file_path("read_only_directory");
+        fs::create_dir_all(&directory).unwrap();
+        fs::set_permissions(&directory, fs::Permissions::from_mode(0o555)).unwrap();
+        let tmp_file_path = directory.join("upload.txt");
+        let resource = format!("/{}", tmp_file_path.display());
+
+        // Root can write to the directory regardless of its permissions
+        if fs::File::create(&tmp_file_path).is_err() {
+            for request in [
+                request_post_builder(&resource).body(b"data").build(),
+                request_put_builder(&resource).body(b"data").build(),
+            ] {
+                let response = build_http_response(&request, &ServerConfig::default());
+                assert_eq!(
+                    response.status_code,
+                    ResponseCode::Error(ErrorCode::Forbidden)
+                );
+            }
+        }
+        let _ = fs::remove_file(&tmp_file_path);
+        fs::set_permissions(&directory, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn response_error_body_negotiated() {
+        let cases = [
+            (
+                Some("application/json"),
+                "application/json",
+                r#"{"error":"Not Found","code":404}"#,
+            ),
+            (
+                Some("text/html,application/xhtml+xml,*/*;q=0.8"),
+                "text/html",
+                "<!DOCTYPE html>\n<html>\n<head><title>404 Not Found</title></head>\n\
+                 <body><h1>404 Not Found</h1></body>\n</html>\n",
+            ),
+            (Some("text/plain"), "text/plain", "404 Not Found"),
+            (Some("*/*"), "text/plain", "404 Not Found"),
+            (None, "text/plain", "404 Not Found"),
+        ];
+
+        for (accept, content_type, body) in cases {
+            let mut request_builder = request_get_builder("/nonexistent_file");
+            if let Some(accept) = accept {
+                request_builder = request_builder.header("Accept", accept);
+            }
+            let response = build_http_response(&request_builder.build(), &ServerConfig::default());
+
+            assert_eq!(
+                response.status_code,
+                ResponseCode::Error(ErrorCode::NotFound)
+            );
+            assert_eq!(
+                response.content.get_header("content-type").unwrap(),
+                content_type
+            );
+            assert_eq!(response.content.get_body(), body.as_bytes());
+            assert_eq!(
+                response.content.get_header("content-length").unwrap(),
+                &body.len().to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn response_unauthorized_request() {
+        let request = request_get_builder("/test")
+            .set_auth_info((AuthMethod::Basic, String::from("djkfdskjf")))
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
+
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Error(ErrorCode::Unauthorized)
+        );
+    }
+
+    #[test]
+    fn response_authorized_request() {
+        let request = request_get_builder("/test")
+            .header(
+                "authorization",
+                format!("Basic {}", encoded_test_credentials()),
+            )
+            .build();
+        let response = build_http_response(&request, &authenticated_config());
+
+        assert_ne!(
+            response.status_code,
+            ResponseCode::Error(ErrorCode::Unauthorized)
+        );
+    }
+
+    #[test]
+    fn response_protected_path() {
+        let config = ServerConfig {
+            protected_paths: vec![String::from("/src")],
+            ..authenticated_config()
+        };
+
+        let request = request_get_builder(TEST_FILE).build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Error(ErrorCode::Unauthorized)
+        );
+        assert_eq!(
+            response.content.get_header("www-authenticate").unwrap(),
+            "Basic"
+        );
+
+        let request = request_get_builder(TEST_FILE)
+            .set_auth_info((AuthMethod::Basic, encoded_test_credentials()))
+            .build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+
+        // Nobody gets in without configured credentials
+        let response = build_http_response(
+            &request,
+            &ServerConfig {
+                protected_paths: vec![String::from("/src")],
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Error(ErrorCode::Unauthorized)
+        );
+
+        let request = request_get_builder("/Cargo.toml").build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+
+        // The same file through a backslash
+        let request = request_get_builder("/%5Csrc/main.rs").build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Error(ErrorCode::Unauthorized)
+        );
+        for request in [
+            request_put_builder("/%5Csrc/new.rs").body(b"data").build(),
+            request_delete_builder("/%5Csrc/main.rs").build(),
+        ] {
+            let response = build_http_response(&request, &config);
+            assert_eq!(
+                response.status_code,
+                ResponseCode::Error(ErrorCode::Unauthorized)
+            );
+        }
+    }
+
+    #[test]
+    fn response_with_invalid_request_bad_request() {
+        let invalid_requests = [
+            String::from(""),
+            String::from("GET /"),
+            String::from("GET / HTTP/1.1\r\nHost: localhost\r\nWrongHeader=value"),
+        ];
+
+        for invalid_request in invalid_requests {
+            let error_response = generate_error_response_for(&invalid_request);
+            assert_eq!(
+                error_response.status_code,
+                ResponseCode::Error(ErrorCode::BadRequest)
+            );
+            assert_eq!(
+                error_response.content.get_header("content-type").unwrap(),
+                "text/plain"
+            );
+            assert_eq!(error_response.content.get_body(), b"400 Bad Request");
+        }
+    }
+
+    #[test]
+    fn response_with_invalid_request_internal_server_error() {
+        let invalid_requests = [String::from(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nContent-Length: -32\r\n\r\n",
+        )];
+
+        for invalid_request in invalid_requests {
+            let error_response = generate_error_response_for(&invalid_request);
+            assert_eq!(
+                error_response.status_code,
+                ResponseCode::Error(ErrorCode::InternalServerError)
+            );
+        }
+    }
+
+    #[test]
+    fn response_with_invalid_request_content_too_large() {
+        let invalid_request = format!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n",
+            MAX_REQUEST_BODY_SIZE + 1
+        );
+        let error_response = generate_error_response_for(&invalid_request);
+
+        assert_eq!(
+            error_response.status_code,
+            ResponseCode::Error(ErrorCode::ContentTooLarge)
+        );
+    }
+
+    #[test]
+    fn response_with_invalid_request_not_accepted() {
+        let not_supported_encoding = ContentEncoding::Pack200gzip.to_string();
+        let invalid_request = format!(
+            "GET /echo/test HTTP/1.1\r\nHost: localhost\r\nAccept-Encoding: {}",
+            not_supported_encoding
+        );
+        let error_response = generate_error_response_for(&invalid_request);
+
+        assert_eq!(
+            error_response.status_code,
+            ResponseCode::Error(ErrorCode::NotAcceptable)
+        );
+    }
+
+    #[test]
+    fn response_with_invalid_request_uri_too_long() {
+        let invalid_request = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\n",
+            ["X"; MAX_URI_LENGTH + 2].concat()
+        );
+        let error_response = generate_error_response_for(&invalid_request);
+
+        assert_eq!(
+            error_response.status_code,
+            ResponseCode::Error(ErrorCode::URITooLong)
+        );
+    }
+
+    #[test]
+    fn response_with_invalid_request_header_too_large() {
+        let invalid_request = format!(
+            "GET / HTTP/1.1\r\nHost: localhost\r\ntest:{}",
+            ["X"; MAX_HEADER_SIZE as usize].concat()
+        );
+        let error_response = generate_error_response_for(&invalid_request);
+
+        assert_eq!(
+            error_response.status_code,
+            ResponseCode::Error(ErrorCode::RequestHeaderFieldsTooLarge)
+        );
+    }
+
+    #[test]
+    fn response_with_invalid_request_http_version_not_supported() {
+        let invalid_request = "GET / HTTP/3.0\r\n";
+        let error_response = generate_error_response_for(invalid_request);
+
+        assert_eq!(
+            error_response.status_code,
+            ResponseCode::Error(ErrorCode::HTTPVersionNotSupported)
+        );
+    }
+
+    #[test]
+    fn read_file_parts_range() {
+        let tmp_file_path = temp_dir().join("http_server_read_file_content_range.txt");
+        fs::write(&tmp_file_path, b"0123456789").expect("Failed to create test file");
+
+        let file = fs::File::open(&tmp_file_path).expect("Can't open test file");
+        let file_content = read_file_parts(&file, &[FilePart::range(&Range::new(2, 6))]).unwrap();
+        assert_eq!(file_content, b"23456");
+
+        let parts = [
+            FilePart::range(&Range::new(9, 9)),
+            FilePart::Bytes(b"|".to_vec()),
+            FilePart::range(&Range::new(0, 1)),
+        ];
+        assert_eq!(read_file_parts(&file, &parts).unwrap(), b"9|01");
+
+        let parts = [FilePart::Section { from: 0, len: 10 }];
+        assert_eq!(read_file_parts(&file, &parts).unwrap(), b"0123456789");
+
+        // The file is shorter than announced
+        let parts = [FilePart::Section { from: 5, len: 10 }];
+        assert!(read_file_parts(&file, &parts).is_err());
+    }
+
+    #[test]
+    fn response_json() {
+        #[derive(Serialize)]
+        struct Status {
+            name: &'static str,
+            uptime: u64,
+        }
+
+        let response =
+            HttpResponseBuilder::new(ResponseCode::Success(SuccessCode::Ok), "1.1", None)
+                .json(&Status {
+                    name: "http-server",
+                    uptime: 42,
+                })
+                .build();
+
+        let expected_body = br#"{"name":"http-server","uptime":42}"#;
+        assert_eq!(
+            response.content.get_header("content-type").unwrap(),
+            "application/json"
+        );
+        assert_eq!(
+            response.content.get_header("content-length").unwrap(),
+            &expected_body.len().to_string()
+        );
+        assert_eq!(response.content.get_body(), expected_body);
+    }
+
+    #[test]
+    fn response_json_file_content_type() {
+        let content = HttpMessageContent::new(HashMap::new(), Vec::new());
+        assert_eq!(
+            content
+                .get_content_type("data/config.json", &HashMap::new())
+                .unwrap(),
+            "application/json"
+        );
+    }
+
+    #[test]
+    fn response_chunked() {
+        let body: Vec<u8> = (0..CHUNK_SIZE * 2 + 100).map(|i| (i % 251) as u8).collect();
+        let response = HttpResponseBuilder::default(ResponseCode::Success(SuccessCode::Ok))
+            .streaming(true)
+            .body(&body)
+            .build();
+
+        let mut stream = Cursor::new(Vec::new());
+        response.write_to(&mut stream).unwrap();
+        let (head, decoded_body) = decode_chunked_response(stream.get_ref());
+
+        assert!(head.contains("transfer-encoding: chunked\r\n"));
+        assert!(!head.contains("content-length"));
+        assert_eq!(decoded_body, body);
+        assert_eq!(response.as_bytes(), *stream.get_ref());
+    }
+
+    #[test]
+    fn response_chunked_buffered_writes() {
+        let body: Vec<u8> = (0..CHUNK_SIZE * 2 + 100).map(|i| (i % 251) as u8).collect();
+        let response = HttpResponseBuilder::default(ResponseCode::Success(SuccessCode::Ok))
+            .streaming(true)
+            .body(&body)
+            .build();
+
+        let mut unbuffered_stream = MockTcpStream::default();
+        response.write_internal(&mut unbuffered_stream).unwrap();
+        let mut stream = MockTcpStream::default();
+        response.write_to(&mut stream).unwrap();
+
+        assert_eq!(stream.written(), unbuffered_stream.written());
+        assert!(stream.write_calls() < unbuffered_stream.write_calls());
+        // Every chunk is as large as the buffer, only the framing around them is batched
+        assert!(stream.write_calls() <= 2 * body.len().div_ceil(CHUNK_SIZE) + 1);
+    }
+
+    // POST REQUEST TESTS
+    #[test]
+    fn response_post() {
+        let tmp_file_path = get_tmp_file_path("test.txt");
+        let _ = fs::remove_file(&tmp_file_path);
+        let file_data = b"data for testing POST request".to_vec();
+
+        let request = request_post_builder(&tmp_file_path.display().to_string())
+            .body(&file_data)
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
+
+        let mut file = fs::File::open(&tmp_file_path).expect("POST request failed to create file");
+        let mut file_content_create_by_post_request = Vec::new();
+        file.read_to_end(&mut file_content_create_by_post_request)
+            .expect("Failed to read test file");
+
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Success(SuccessCode::Created)
+        );
+        assert_eq!(file_content_create_by_post_request, file_data);
+    }
+
+    #[test]
+    fn response_post_existing_file() {
+        let tmp_file_path = get_tmp_file_path("test_post_conflict.txt");
+        fs::write(&tmp_file_path, b"original").unwrap();
+
+        let request = request_post_builder(&tmp_file_path.display().to_string())
+            .body(b"replacement")
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
+
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Error(ErrorCode::Conflict)
+        );
+        assert_eq!(read_file(&tmp_file_path), b"original");
+    }
+
+    #[test]
+    fn response_post_invalid() {
+        let request = request_post_builder("/nonexistent/test").build();
+        let response = build_http_response(&request, &ServerConfig::default());
+
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Error(ErrorCode::NotFound)
+        );
+    }
+
+    #[test]
+    fn response_post_multipart_form() {
+        let tmp_file_path = get_tmp_file_path("test_multipart.txt");
+        let _ = fs::remove_file(&tmp_file_path);
+        let boundary = "----FormBoundary";
+        let body = format!(
+            "--{boundary}\r\n\
+             Content-Disposition: form-data; name=\"description\"\r\n\r\n\
+             a text field\r\n\
+             --{boundary}\r\n\
+             Content-Disposition: form-data; name=\"file\"; filename=\"upload.txt\"\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             uploaded file content\r\n\
+             --{boundary}--\r\n"
+        );
+
+        let request = request_post_builder(&tmp_file_path.display().to_string())
+            .header(
+                "content-type",
+                format!("multipart/form-data; boundary={}", boundary),
+            )
+            .body(body.as_bytes())
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
+
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Success(SuccessCode::Created)
+        );
+        assert_eq!(read_file(&tmp_file_path), b"uploaded file content");
+    }
+
+    #[test]
+    fn response_put() {
+        let tmp_file_path = get_tmp_file_path("test_put.txt");
+        let _ = fs::remove_file(&tmp_file_path);
+        let resource = tmp_file_path.display().to_string();
+
+        let request = request_put_builder(&resource)
+            .body(b"first version")
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Success(SuccessCode::Created)
+        );
+        assert_eq!(read_file(&tmp_file_path), b"first version");
+
+        let request = request_put_builder(&resource).body(b"second").build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+        assert_eq!(read_file(&tmp_file_path), b"second");
+    }
+
+    #[test]
+    fn response_delete() {
+        let tmp_file_path = get_tmp_file_path("test_delete.txt");
+        fs::write(&tmp_file_path, b"to be deleted").unwrap();
+        let resource = tmp_file_path.display().to_string();
+
+        let request = request_delete_builder(&resource).build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Success(SuccessCode::NoContent)
+        );
+        assert!(response.content.get_header("content-length").is_none());
+        assert!(!tmp_file_path.exists());
+
+        let request = request_delete_builder(&resource).build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Error(ErrorCode::NotFound)
+        );
+
+        let request = request_delete_builder("/target/tmp/../Cargo.toml").build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Error(ErrorCode::Forbidden)
+        );
+    }
+
+    #[test]
+    fn response_post_gzip_body() {
+        let tmp_file_path = get_tmp_file_path("test_gzip.txt");
+        let _ = fs::remove_file(&tmp_file_path);
+        let file_data = b"data for testing compressed POST request";
+        let compressed_data = Compressor::compress(file_data, ContentEncoding::Gzip).unwrap();
+
+        let mut raw_request = format!(
+            "POST /{} HTTP/1.1\r\nHost: localhost\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+            tmp_file_path.display(),
+            compressed_data.len()
+        )
+        .into_bytes();
+        raw_request.extend_from_slice(&compressed_data);
+        let request = parse_http_request(&mut Cursor::new(raw_request)).unwrap();
+        let response = build_http_response(&request, &ServerConfig::default());
+
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Success(SuccessCode::Created)
+        );
+        assert_eq!(fs::read(&tmp_file_path).unwrap(), file_data);
+    }
+
+    #[test]
+    fn response_post_invalid_gzip_body() {
+        let invalid_request =
+            "POST /test.txt HTTP/1.1\r\nHost: localhost\r\nContent-Encoding: gzip\r\nContent-Length: 4\r\n\r\ntest";
+        let error_response = generate_error_response_for(invalid_request);
+
+        assert_eq!(
+            error_response.status_code,
+            ResponseCode::Error(ErrorCode::BadRequest)
+        );
+    }
+
+    // HEAD requests
+    #[test]
+    fn response_head_file() {
+        let request = request_head_builder(TEST_FILE).build();
+        let response = build_http_response(&request, &ServerConfig::default());
+
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+        assert_eq!(
+            response.content.get_header("content-type").unwrap(),
+            "text/x-rust"
+        );
+
+        assert!(response.content.get_body().is_empty());
+    }
+
+    #[test]
+    fn response_head_large_file() {
+        let tmp_file_path = get_tmp_file_path("test_head_large.bin");
+        let file_len = 8 * 1024 * 1024;
+        fs::File::create(&tmp_file_path)
+            .unwrap()
+            .set_len(file_len)
+            .unwrap();
+
+        let request = request_head_builder(&tmp_file_path.display().to_string()).build();
+        let response = build_http_response(&request, &ServerConfig::default());
+
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+        assert_eq!(
+            response.content.get_header("content-length").unwrap(),
+            &file_len.to_string()
+        );
+        assert!(response.content.get_body().is_empty());
+        assert_eq!(response.content.get_body().capacity(), 0);
+    }
+
+    #[test]
+    fn response_head_headers_match_get() {
+        let small_file_path = get_tmp_file_path("test_head_small.txt");
+        fs::write(&small_file_path, "small").unwrap();
+        let large_file_path = get_tmp_file_path("test_head_streamed.txt");
+        fs::write(&large_file_path, "a".repeat(STREAMING_THRESHOLD as usize)).unwrap();
+        let echo = format!("/echo/{}", "a".repeat(MIN_COMPRESSION_SIZE));
+
+        // Small and streamed files are sent as is
+        let resources = [
+            (TEST_FILE.to_string(), Some("gzip")),
+            (small_file_path.display().to_string(), None),
+            (large_file_path.display().to_string(), None),
+            (echo, Some("gzip")),
+        ];
+        for (resource, content_encoding) in &resources {
+            let headers = |request_builder: HttpRequestBuilder| {
+                let request = request_builder.set_encoding(ContentEncoding::Gzip).build();
+                let mut headers = build_http_response(&request, &ServerConfig::default())
+                    .content
+                    .get_headers()
+                    .clone();
+                headers.remove("date");
+                headers
+            };
+
+            let head_headers = headers(request_head_builder(resource));
+            assert_eq!(head_headers, headers(request_get_builder(resource)));
+            assert_eq!(
+                head_headers.get("content-encoding").map(String::as_str),
+                *content_encoding
+            );
+        }
+    }
+
+    #[test]
+    fn response_get_large_file_streamed() {
+        let tmp_file_path = get_tmp_file_path("test_streamed.bin");
+        let file_content: Vec<u8> = (0..STREAMING_THRESHOLD + 12345)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        fs::write(&tmp_file_path, &file_content).unwrap();
+
+        let request = request_get_builder(&tmp_file_path.display().to_string())
+            .set_encoding(ContentEncoding::Gzip)
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
+
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+        assert!(response.has_file_body());
+        // Nothing is buffered in memory
+        assert_eq!(response.content.get_body().capacity(), 0);
+        assert!(response.content.get_header("content-encoding").is_none());
+        assert_eq!(
+            response.content.get_header("content-length").unwrap(),
+            &file_content.len().to_string()
+        );
+
+        let raw_response = response.as_bytes();
+        let headers_end = raw_response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .unwrap();
+        assert_eq!(&raw_response[headers_end + 4..], file_content.as_slice());
+    }
+
+    #[test]
+    fn response_get_large_ranges_streamed() {
+        let tmp_file_path = get_tmp_file_path("test_streamed_ranges.bin");
+        let file_len = 2 * STREAMING_THRESHOLD;
+        let file_content: Vec<u8> = (0..file_len).map(|i| (i % 251) as u8).collect();
+        fs::write(&tmp_file_path, &file_content).unwrap();
+
+        fn response_body(response: &HttpResponse) -> Vec<u8> {
+            let raw_response = response.as_bytes();
+            let headers_end = raw_response
+                .windows(4)
+                .position(|window| window == b"\r\n\r\n")
+                .unwrap();
+            raw_response[headers_end + 4..].to_vec()
+        }
+
+        let request = request_get_builder(&tmp_file_path.display().to_string())
+            .set_range("bytes=10-".parse().unwrap())
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Success(SuccessCode::PartialContent)
+        );
+        assert!(response.has_file_body());
+        assert_eq!(response.content.get_body().capacity(), 0);
+        assert_eq!(
+            response.content.get_header("content-length").unwrap(),
+            &(file_len - 10).to_string()
+        );
+        assert_eq!(response_body(&response), &file_content[10..]);
+
+        let request = request_get_builder(&tmp_file_path.display().to_string())
+            .set_range("bytes=0-9, 1000-1049575".parse().unwrap())
+            .build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert!(response.has_file_body());
+        assert_eq!(response.content.get_body().capacity(), 0);
+
+        let body = response_body(&response);
+        assert_eq!(
+            response.content.get_header("content-length").unwrap(),
+            &body.len().to_string()
+        );
+        let boundary = HttpResponse::partial_content_boundary();
+        let mut expected = format!(
+            "--{}\r\ncontent-type: application/octet-stream\r\ncontent-range: bytes 0-9/{}\r\n\r\n",
+            boundary, file_len
+        )
+        .into_bytes();
+        expected.extend_from_slice(&file_content[..10]);
+        expected.extend_from_slice(
+            format!(
+                "\r\n--{}\r\ncontent-type: application/octet-stream\r\ncontent-range: bytes 1000-1049575/{}\r\n\r\n",
+                boundary, file_len
+            )
+            .as_bytes(),
+        );
+        expected.extend_from_slice(&file_content[1000..=1049575]);
+        expected.extend_from_slice(b"\r\n");
+        assert_eq!(body, expected);
+    }
+
+    fn create_test_directory(name: &str) -> PathBuf {
+        let directory = get_tmp_file_path(name);
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(directory.join("nested")).unwrap();
+        fs::write(directory.join("first.txt"), "first").unwrap();
+        fs::write(directory.join("a <b>.txt"), "second").unwrap();
+        directory
+    }
+
+    #[test]
+    fn response_get_directory_listing() {
+        let directory = create_test_directory("test_listing");
+        let config = ServerConfig {
+            directory_listing: true,
+            ..Default::default()
+        };
+
+        let request = request_get_builder(&format!("{}/", directory.display())).build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+        assert_eq!(
+            response.content.get_header("content-type").unwrap(),
+            "text/html"
+        );
+
+        let listing = String::from_utf8(response.content.get_body().clone()).unwrap();
+        let base = directory.display().to_string();
+        assert!(listing.contains(&format!("<a href=\"/{}/first.txt\">first.txt</a>", base)));
+        assert!(listing.contains(&format!("<a href=\"/{}/nested/\">nested/</a>", base)));
+        assert!(listing.contains(&format!(
+            "<a href=\"/{}/a%20&lt;b&gt;.txt\">a &lt;b&gt;.txt</a>",
+            base
+        )));
+    }
+
+    #[test]
+    fn response_get_directory_listing_disabled() {
+        let directory = create_test_directory("test_listing_disabled");
+
+        let request = request_get_builder(&format!("{}/", directory.display())).build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Error(ErrorCode::NotFound)
+        );
+        assert_eq!(response.content.get_body(), b"404 Not Found");
+    }
+
+    #[test]
+    fn response_get_directory_index_file() {
+        let directory = create_test_directory("test_index_file");
+        fs::write(directory.join("index.html"), "<h1>Home</h1>").unwrap();
+        fs::write(directory.join("home.htm"), "<h1>Other home</h1>").unwrap();
+
+        let request = request_get_builder(&format!("{}/", directory.display())).build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+        assert_eq!(
+            response.content.get_header("content-type").unwrap(),
+            "text/html"
+        );
+        assert_eq!(response.content.get_body(), b"<h1>Home</h1>");
+
+        let config = ServerConfig {
+            index_file: String::from("home.htm"),
+            ..Default::default()
+        };
+        let response = build_http_response(&request, &config);
+        assert_eq!(response.content.get_body(), b"<h1>Other home</h1>");
+    }
+
+    #[test]
+    fn response_get_directory_without_index_file() {
+        let directory = create_test_directory("test_without_index_file");
+        let request = request_get_builder(&format!("{}/", directory.display())).build();
+
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Error(ErrorCode::NotFound)
+        );
+
+        let config = ServerConfig {
+            directory_listing: true,
+            ..Default::default()
+        };
+        let response = build_http_response(&request, &config);
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+        let listing = String::from_utf8(response.content.get_body().clone()).unwrap();
+        assert!(listing.contains("first.txt"));
+    }
+
+    #[test]
+    fn response_redirect() {
+        let response = HttpResponseBuilder::default(ResponseCode::Success(SuccessCode::Ok))
+            .redirect("/new/location", true)
+            .build();
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Success(SuccessCode::MovedPermanently)
+        );
+        assert_eq!(
+            response.content.get_header("location").unwrap(),
+            "/new/location"
+        );
+        assert!(response
+            .as_bytes()
+            .starts_with(b"HTTP/1.1 301 Moved Permanently\r\n"));
+
+        let response = HttpResponseBuilder::default(ResponseCode::Success(SuccessCode::Ok))
+            .redirect("/temporary", false)
+            .build();
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Success(SuccessCode::Found)
+        );
+        assert_eq!(
+            response.content.get_header("location").unwrap(),
+            "/temporary"
+        );
+    }
+
+    #[test]
+    fn response_get_directory_without_trailing_slash() {
+        let directory = create_test_directory("test_trailing_slash");
+        let resource = directory.display().to_string();
+
+        let request = request_get_builder(&format!("{}?sort=name", resource)).build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Success(SuccessCode::MovedPermanently)
+        );
+        assert_eq!(
+            response.content.get_header("location").unwrap(),
+            &format!("{}/?sort=name", resource)
+        );
+
+        // Regular files are served as usual
+        let request = request_get_builder(&format!("{}/first.txt", resource)).build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+    }
+
+    #[test]
+    fn response_hidden_files() {
+        let root = get_tmp_file_path("test_hidden_root");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join(".env"), "SECRET=1").unwrap();
+        fs::write(root.join(".git").join("config"), "[core]").unwrap();
+        fs::write(root.join("normal.txt"), "visible").unwrap();
+        fs::create_dir_all(root.join(".well-known")).unwrap();
+        fs::write(
+            root.join(".well-known").join("security.txt"),
+            "Contact: a@b.c",
+        )
+        .unwrap();
+        let config = ServerConfig {
+            root: root.clone(),
+            ..Default::default()
+        };
+
+        for resource in [
+            "/.env",
+            "/.git/config",
+            "/.git/",
+            "/.cache",
+            "/.well-known/security.txt",
+        ] {
+            let request = request_get_builder(resource).build();
+            let response = build_http_response(&request, &config);
+            assert_eq!(
+                response.status_code,
+                ResponseCode::Error(ErrorCode::Forbidden),
+                "{}",
+                resource
+            );
+        }
+        let request = request_put_builder("/.env").body(b"SECRET=2").build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Error(ErrorCode::Forbidden)
+        );
+        assert_eq!(read_file(&root.join(".env")), b"SECRET=1");
+
+        let request = request_get_builder("/normal.txt").build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+
+        let config = ServerConfig {
+            allowed_hidden_files: vec![String::from(".well-known")],
+            ..config
+        };
+        let request = request_get_builder("/.well-known/security.txt").build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+        assert_eq!(response.content.get_body(), b"Contact: a@b.c");
+        let request = request_get_builder("/.env").build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Error(ErrorCode::Forbidden)
+        );
+    }
+
+    #[test]
+    fn response_get_virtual_hosts() {
+        let mut virtual_hosts = HashMap::new();
+        for host in ["a.test", "b.test"] {
+            let root = get_tmp_file_path(&format!("test_vhost_{}", host));
+            fs::create_dir_all(&root).unwrap();
+            fs::write(root.join("index.txt"), format!("served for {}", host)).unwrap();
+            virtual_hosts.insert(host.to_string(), root);
+        }
+        let mut config = ServerConfig {
+            virtual_hosts,
+            ..Default::default()
+        };
+
+        for (host, body) in [
+            ("a.test", "served for a.test"),
+            ("B.test:8080", "served for b.test"),
+        ] {
+            let request = request_get_builder("/index.txt")
+                .header("Host", host)
+                .build();
+            let response = build_http_response(&request, &config);
+            assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+            assert_eq!(response.content.get_body(), body.as_bytes());
+        }
+
+        // Other hosts are served from the default root, unless only the configured ones are allowed
+        let request = request_get_builder(TEST_FILE)
+            .header("Host", "c.test")
+            .build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+
+        config.strict_virtual_hosts = true;
+        let response = build_http_response(&request, &config);
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Error(ErrorCode::NotFound)
+        );
+    }
+
+    #[test]
+    fn response_get_fallback_roots() {
+        let roots: Vec<PathBuf> = ["test_first_root", "test_second_root"]
+            .iter()
+            .map(|name| get_tmp_file_path(name))
+            .collect();
+        for root in &roots {
+            let _ = fs::remove_dir_all(root);
+            fs::create_dir_all(root).unwrap();
+            fs::write(root.join("both.txt"), format!("from {}", root.display())).unwrap();
+        }
+        fs::write(roots[1].join("second.txt"), "only in the second root").unwrap();
+        let config = ServerConfig {
+            root: roots[0].clone(),
+            fallback_roots: vec![roots[1].clone()],
+            ..Default::default()
+        };
+
+        let request = request_get_builder("/second.txt").build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+        assert_eq!(response.content.get_body(), b"only in the second root");
+
+        let request = request_get_builder("/both.txt").build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(
+            response.content.get_body(),
+            format!("from {}", roots[0].display()).as_bytes()
+        );
+
+        let request = request_get_builder("/missing.txt").build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Error(ErrorCode::NotFound)
+        );
+
+        // Files of the fallback roots can't be replaced through the first root
+        let request = request_delete_builder("/second.txt").build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Error(ErrorCode::NotFound)
+        );
+        assert!(roots[1].join("second.txt").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn response_get_symlink_policy() {
+        let root = get_tmp_file_path("test_symlink_root");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("target.txt"), "behind a symlink").unwrap();
+        // Relative targets are resolved from the directory of the link
+        std::os::unix::fs::symlink("target.txt", root.join("link.txt")).unwrap();
+        let outside = get_tmp_file_path("symlink_outside.txt");
+        fs::write(&outside, "outside the root").unwrap();
+        std::os::unix::fs::symlink(fs::canonicalize(&outside).unwrap(), root.join("escape.txt"))
+            .unwrap();
+        let mut config = ServerConfig {
+            root: root.clone(),
+            ..Default::default()
+        };
+
+        for resource in ["/link.txt", "/escape.txt"] {
+            let request = request_get_builder(resource).build();
+            let response = build_http_response(&request, &config);
+            assert_eq!(
+                response.status_code,
+                ResponseCode::Error(ErrorCode::Forbidden),
+                "{}",
+                resource
+            );
+        }
+
+        config.follow_symlinks = true;
+        for (resource, body) in [
+            ("/link.txt", "behind a symlink"),
+            ("/escape.txt", "outside the root"),
+        ] {
+            let request = request_get_builder(resource).build();
+            let response = build_http_response(&request, &config);
+            assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+            assert_eq!(response.content.get_body(), body.as_bytes());
+        }
+    }
+
+    #[test]
+    fn response_get_file_under_root() {
+        let root = get_tmp_file_path("test_root");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("inside.txt"), "inside the root").unwrap();
+        fs::write(get_tmp_file_path("outside.txt"), "outside the root").unwrap();
+        let config = ServerConfig {
+            root: root.clone(),
+            ..Default::default()
+        };
+
+        let request = request_get_builder("/inside.txt").build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+        assert_eq!(response.content.get_body(), b"inside the root");
+
+        // Paths are relative to the root, not to the working directory
+        let request = request_get_builder(TEST_FILE).build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Error(ErrorCode::NotFound)
+        );
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(
+                fs::canonicalize(get_tmp_file_path("outside.txt")).unwrap(),
+                root.join("link.txt"),
+            )
+            .unwrap();
+            let request = request_get_builder("/link.txt").build();
+            let response = build_http_response(&request, &config);
+            assert_eq!(
+                response.status_code,
+                ResponseCode::Error(ErrorCode::Forbidden)
+            );
+
+            let request = request_put_builder("/link.txt")
+                .body(b"overwritten")
+                .build();
+            let response = build_http_response(&request, &config);
+            assert_eq!(
+                response.status_code,
+                ResponseCode::Error(ErrorCode::Forbidden)
+            );
+            assert_eq!(
+                fs::read(get_tmp_file_path("outside.txt")).unwrap(),
+                b"outside the root"
+            );
+        }
+    }
+
+    // OPTIONS requests
+    #[test]
+    fn response_options() {
+        let options_request = request_options_builder(TEST_FILE).build();
+        let response = build_http_response(&options_request, &ServerConfig::default());
+
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+        assert!(response.content().get_header("allow").is_some());
+    }
+
+    #[test]
+    fn response_cors_preflight() {
+        let config = ServerConfig {
+            cors_allowed_origins: vec![String::from("https://example.com")],
+            ..Default::default()
+        };
+        let request = request_options_builder("/upload")
+            .header("Origin", "https://example.com")
+            .header("Access-Control-Request-Method", "PUT")
+            .header("Access-Control-Request-Headers", "Content-Type, X-Custom")
+            .build();
+        let response = build_http_response(&request, &config);
+
+        assert_eq!(
+            response.status_code,
+            ResponseCode::Success(SuccessCode::NoContent)
+        );
+        assert_eq!(
+            response
+                .content
+                .get_header("access-control-allow-origin")
+                .unwrap(),
+            "https://example.com"
+        );
+        assert_eq!(
+            response
+                .content
+                .get_header("access-control-allow-methods")
+                .unwrap(),
+            "GET, HEAD, POST, PUT, DELETE, OPTIONS, TRACE"
+        );
+        assert_eq!(
+            response
+                .content
+                .get_header("access-control-allow-headers")
+                .unwrap(),
+            "Content-Type, X-Custom"
+        );
+
+        // Unknown origins don't get any of the CORS headers
+        let request = request_options_builder(TEST_FILE)
+            .header("Origin", "https://evil.example")
+            .header("Access-Control-Request-Method", "PUT")
+            .build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+        assert!(response
+            .content
+            .get_header("access-control-allow-origin")
+            .is_none());
+    }
+
+    #[test]
+    fn response_cors_simple_request() {
+        let config = ServerConfig {
+            cors_allowed_origins: vec![String::from("https://example.com")],
+            ..Default::default()
+        };
+        let request = request_get_builder("/echo/hello")
+            .header("Origin", "https://example.com")
+            .build();
+        let response = build_http_response(&request, &config);
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+        assert_eq!(
+            response
+                .content
+                .get_header("access-control-allow-origin")
+                .unwrap(),
+            "https://example.com"
+        );
+        assert_eq!(response.content.get_header("vary").unwrap(), "Origin");
+
+        let config = ServerConfig {
+            cors_allowed_origins: vec![String::from("*")],
+            ..Default::default()
+        };
+        let response = build_http_response(&request, &config);
+        assert_eq!(
+            response
+                .content
+                .get_header("access-control-allow-origin")
+                .unwrap(),
+            "*"
+        );
+
+        // Same-origin requests don't send `Origin`
+        let request = request_get_builder("/echo/hello").build();
+        let response = build_http_response(&request, &config);
+        assert!(response
+            .content
+            .get_header("access-control-allow-origin")
+            .is_none());
+    }
+
+    #[test]
+    fn response_security_headers() {
+        let request = request_get_builder("/echo/hello").build();
+        let response = build_http_response(&request, &ServerConfig::default());
+        assert_eq!(
+            response
+                .content
+                .get_header("x-content-type-options")
+                .unwrap(),
+            "nosniff"
+        );
+        assert_eq!(
+            response.content.get_header("x-frame-options").unwrap(),
+            "DENY"
+        );
+        assert_eq!(
+            response
+                .content
+                .get_header("content-security-policy")
+                .unwrap(),
+            "default-src 'self'"
+        );
+
+        let config = ServerConfig {
+            content_security_policy: String::from("default-src 'none'"),
+            ..Default::default()
+        };
+        let response = build_http_response(&request, &config);
+        assert_eq!(
+            response
+                .content
+                .get_header("content-security-policy")
+                .unwrap(),
+            "default-src 'none'"
+        );
+
+        let config = ServerConfig {
+            security_headers: false,
+            ..Default::default()
+        };
+        let response = build_http_response(&request, &config);
+        for header in [
+            "x-content-type-options",
+            "x-frame-options",
+            "content-security-policy",
+        ] {
+            assert!(response.content.get_header(header).is_none());
+        }
+    }
+
+    #[test]
+    fn response_method_not_allowed() {
+        let requests = [
+            request_post_builder("/echo/x").build(),
+            request_delete_builder("/user-agent").build(),
+            request_put_builder("/").build(),
+        ];
+        for request in requests {
+            let response = build_http_response(&request, &ServerConfig::default());
+            assert_eq!(
+                response.status_code,
+                ResponseCode::Error(ErrorCode::MethodNotAllowed)
+            );
+            assert_eq!(
+                response.content.get_header("allow").unwrap(),
+                "GET, HEAD, OPTIONS, TRACE"
+            );
+        }
+
+        let options_request = request_options_builder("/echo/x.txt").build();
+        let response = build_http_response(&options_request, &ServerConfig::default());
+        assert_eq!(response.status_code, ResponseCode::Success(SuccessCode::Ok));
+        assert_eq!(
+            response.content.get_header("allow").unwrap(),
+            "GET, HEAD, OPTIONS, TRACE"
+        );
     }
 }
@@ -0,0 +1,115 @@
+use crate::{
+    request::{HttpRequest, HttpRequestMethod},
+    response::HttpResponse,
+};
+
+pub type Handler = fn(&HttpRequest) -> HttpResponse;
+
+#[derive(Debug, Clone)]
+struct Route {
+    method: HttpRequestMethod,
+    // Without the leading and trailing slashes
+    prefix: String,
+    handler: Handler,
+}
+
+// Handlers registered by method and path prefix. Requests that don't match any of them get the
+// built-in behavior
+#[derive(Debug, Clone, Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route(
+        mut self,
+        method: HttpRequestMethod,
+        prefix: impl Into<String>,
+        handler: Handler,
+    ) -> Self {
+        self.routes.push(Route {
+            method,
+            prefix: prefix.into().trim_matches('/').to_string(),
+            handler,
+        });
+        self
+    }
+
+    // The longest matching prefix wins. Prefixes match whole path segments only,
+    // `/hello` doesn't match `/helloworld`
+    pub fn handler(&self, request: &HttpRequest) -> Option<Handler> {
+        let resource = request.get_url().decoded_resource()?;
+        let resource = resource.trim_start_matches('/');
+        self.routes
+            .iter()
+            .filter(|route| route.method == request.get_method())
+            .filter(|route| {
+                route.prefix.is_empty()
+                    || resource
+                        .strip_prefix(&route.prefix)
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .max_by_key(|route| route.prefix.len())
+            .map(|route| route.handler)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use crate::{
+        common::{ResponseCode, SuccessCode},
+        request::parse_http_request,
+        response::HttpResponseBuilder,
+    };
+
+    fn hello(_: &HttpRequest) -> HttpResponse {
+        HttpResponseBuilder::default(ResponseCode::Success(SuccessCode::Ok))
+            .body(b"hello")
+            .build()
+    }
+
+    fn hello_world(_: &HttpRequest) -> HttpResponse {
+        HttpResponseBuilder::default(ResponseCode::Success(SuccessCode::Ok))
+            .body(b"hello world")
+            .build()
+    }
+
+    fn routed_body(router: &Router, request_line: &str) -> Option<Vec<u8>> {
+        let mut stream =
+            Cursor::new(format!("{}\r\nHost: localhost\r\n\r\n", request_line).into_bytes());
+        let request = parse_http_request(&mut stream).unwrap();
+        router
+            .handler(&request)
+            .map(|handler| handler(&request).content().get_body().clone())
+    }
+
+    #[test]
+    fn router_longest_prefix() {
+        let router = Router::new()
+            .route(HttpRequestMethod::GET, "/hello", hello)
+            .route(HttpRequestMethod::GET, "/hello/world/", hello_world);
+
+        assert_eq!(
+            routed_body(&router, "GET /hello HTTP/1.1").unwrap(),
+            b"hello"
+        );
+        assert_eq!(
+            routed_body(&router, "GET /hello/there HTTP/1.1").unwrap(),
+            b"hello"
+        );
+        assert_eq!(
+            routed_body(&router, "GET /hello/world/again HTTP/1.1").unwrap(),
+            b"hello world"
+        );
+        assert!(routed_body(&router, "GET /helloworld HTTP/1.1").is_none());
+        assert!(routed_body(&router, "HEAD /hello HTTP/1.1").is_none());
+    }
+}
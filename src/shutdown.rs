@@ -0,0 +1,31 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+// Clients turned away while draining are told to come back once the server is replaced
+pub const DRAIN_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+// Shared by all connections. Once draining, new connections are answered with 503 and open ones
+// are closed after their current request
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownSignal {
+    draining: Arc<AtomicBool>,
+}
+
+impl ShutdownSignal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start_draining(&self) {
+        self.draining.store(true, Ordering::Release);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Acquire)
+    }
+}
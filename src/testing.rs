@@ -0,0 +1,90 @@
+use std::{
+    io::{self, Read, Write},
+    time::Duration,
+};
+
+use crate::common::HttpStream;
+
+// In-memory connection for tests. Reads drain the request bytes and return 0 once they are
+// exhausted, like a socket the client closed. Everything written is collected separately
+#[derive(Debug, Default, Clone)]
+pub struct MockTcpStream {
+    read_data: Vec<u8>,
+    read_position: usize,
+    write_data: Vec<u8>,
+    write_calls: usize,
+}
+
+impl MockTcpStream {
+    pub fn new(request: &[u8]) -> Self {
+        Self {
+            read_data: request.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    pub fn written(&self) -> &[u8] {
+        &self.write_data
+    }
+
+    // Number of `write` calls, to check how writes reach the socket
+    pub fn write_calls(&self) -> usize {
+        self.write_calls
+    }
+
+    pub fn written_string(&self) -> String {
+        String::from_utf8_lossy(&self.write_data).into_owned()
+    }
+}
+
+impl Read for MockTcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = &self.read_data[self.read_position..];
+        let len = remaining.len().min(buf.len());
+        buf[..len].copy_from_slice(&remaining[..len]);
+        self.read_position += len;
+        Ok(len)
+    }
+}
+
+impl Write for MockTcpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_data.extend_from_slice(buf);
+        self.write_calls += 1;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl HttpStream for MockTcpStream {
+    fn clone_stream(&self) -> Self {
+        self.clone()
+    }
+
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_stream_drains_reads() {
+        let mut stream = MockTcpStream::new(b"hello");
+        let mut buf = [0u8; 3];
+
+        assert_eq!(stream.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"hel");
+        assert_eq!(stream.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"lo");
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+
+        stream.write_all(b"written").unwrap();
+        assert_eq!(stream.written(), b"written");
+    }
+}
@@ -1,21 +1,103 @@
+use anyhow::{anyhow, Result};
+
 #[derive(Debug, Clone)]
 pub struct Url {
     resource: String,
     query: String,
+    // Only set for absolute-form targets, which proxies send
+    authority: Option<String>,
+}
+
+// `http://example.com/path?a=1` -> (`example.com`, `/path?a=1`). User information before `@`
+// is dropped, an empty path becomes `/`
+fn split_absolute_form(data: &str) -> Option<(String, String)> {
+    let (scheme, rest) = data.split_once("://")?;
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+    let authority_end = rest.find(['/', '?']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(authority_end);
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let path = if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/{}", path)
+    };
+    Some((authority.to_string(), path))
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|value| value as u8)
+}
+
+// Decodes `%XX` sequences, `a%20b%C3%A9` -> `a bé`. Fails on a malformed or truncated sequence
+// and when the decoded bytes are not valid UTF-8
+pub fn percent_decode(data: &str) -> Result<String> {
+    let bytes = data.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            decoded.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let escaped = match bytes[i..] {
+            [b'%', high, low, ..] => hex_value(high).zip(hex_value(low)),
+            _ => None,
+        };
+        let (high, low) =
+            escaped.ok_or_else(|| anyhow!("Invalid escape sequence at {} in {:?}", i, data))?;
+        decoded.push(high << 4 | low);
+        i += 3;
+    }
+    String::from_utf8(decoded).map_err(|_| anyhow!("Decoded {:?} is not valid UTF-8", data))
+}
+
+// Every byte but the unreserved characters of RFC 3986 is encoded, `a b/é` -> `a%20b%2F%C3%A9`
+pub fn percent_encode(data: &str) -> String {
+    let mut encoded = String::with_capacity(data.len());
+    for byte in data.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+// Collapses repeated slashes and drops `.` segments, `/a//./b/` -> `/a/b/`. `..` segments are
+// kept, resolving them could hide a traversal from the checks done on the result
+fn normalize_path(path: &str) -> String {
+    let segments: Vec<&str> = path
+        .split('/')
+        .filter(|segment| !segment.is_empty() && *segment != ".")
+        .collect();
+    let mut normalized = segments.join("/");
+    if path.starts_with('/') {
+        normalized.insert(0, '/');
+    }
+    let is_directory = path.ends_with('/') || path.ends_with("/.");
+    if is_directory && !segments.is_empty() {
+        normalized.push('/');
+    }
+    normalized
 }
 
 impl Url {
     pub fn new(data: &str) -> Self {
-        if let Some((resource, query)) = data.split_once('?') {
-            Self {
-                resource: resource.to_string(),
-                query: query.to_string(),
-            }
-        } else {
-            Self {
-                resource: data.to_string(),
-                query: String::from(""),
-            }
+        let (authority, target) = match split_absolute_form(data) {
+            Some((authority, path)) => (Some(authority), path),
+            None => (None, data.to_string()),
+        };
+        let (resource, query) = target.split_once('?').unwrap_or((&target, ""));
+        Self {
+            resource: resource.to_string(),
+            query: query.to_string(),
+            authority,
         }
     }
 
@@ -23,7 +105,170 @@ impl Url {
         self.resource.clone()
     }
 
+    // Resource with `%XX` sequences decoded, `None` if one of them is malformed
+    // or the result is not valid UTF-8.
+    // Decoded `%2F` becomes a regular separator, so the result has to be checked for traversal.
+    // The path is normalized, see `normalize_path`
+    pub fn decoded_resource(&self) -> Option<String> {
+        percent_decode(&self.resource)
+            .ok()
+            .map(|decoded| normalize_path(&decoded))
+    }
+
+    // Authority of an absolute-form target, `example.com:8080` in `http://example.com:8080/index.html`
+    pub fn authority(&self) -> Option<String> {
+        self.authority.clone()
+    }
+
     pub fn query(&self) -> String {
         self.query.clone()
     }
+
+    // Parameters in the order they appear in the query, repeated keys are preserved.
+    // Example: `a=1&flag&q=hello%20world` -> [("a", "1"), ("flag", ""), ("q", "hello world")]
+    pub fn query_params(&self) -> Vec<(String, String)> {
+        // `+` stands for a space in form encoded queries. Malformed parameters are kept as they are
+        let decode = |data: &str| {
+            let data = data.replace('+', " ");
+            percent_decode(&data).unwrap_or(data)
+        };
+
+        self.query
+            .split('&')
+            .filter(|param| !param.is_empty())
+            .map(|param| {
+                let (key, value) = param.split_once('=').unwrap_or((param, ""));
+                (decode(key), decode(value))
+            })
+            .collect()
+    }
+
+    // First value of the query parameter
+    pub fn query_param(&self, key: &str) -> Option<String> {
+        self.query_params()
+            .into_iter()
+            .find(|(param_key, _)| param_key == key)
+            .map(|(_, value)| value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(data: &[(&str, &str)]) -> Vec<(String, String)> {
+        data.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn url_decoded_resource() {
+        let decoded = |data: &str| Url::new(data).decoded_resource();
+
+        assert_eq!(
+            decoded("/files/my%20file.txt").unwrap(),
+            "/files/my file.txt"
+        );
+        assert_eq!(
+            decoded("/files/c%2B%2b.txt?a=%20").unwrap(),
+            "/files/c++.txt"
+        );
+        assert_eq!(decoded("/files/a%2Fb").unwrap(), "/files/a/b");
+        assert_eq!(decoded("/files/plain").unwrap(), "/files/plain");
+        assert_eq!(
+            Url::new("/files/my%20file.txt").resource(),
+            "/files/my%20file.txt"
+        );
+
+        assert_eq!(decoded("/a//b").unwrap(), "/a/b");
+        assert_eq!(decoded("/a/./b").unwrap(), "/a/b");
+        assert_eq!(decoded("/files//sub/./x.txt").unwrap(), "/files/sub/x.txt");
+        assert_eq!(decoded("/a/%2F/b/%2E/").unwrap(), "/a/b/");
+        assert_eq!(decoded("/a/.").unwrap(), "/a/");
+        assert_eq!(decoded("//./").unwrap(), "/");
+        assert_eq!(decoded("/a/../b").unwrap(), "/a/../b");
+        assert_eq!(decoded("*").unwrap(), "*");
+
+        assert!(decoded("/files/file%").is_none());
+        assert!(decoded("/files/file%2").is_none());
+        assert!(decoded("/files/file%zz").is_none());
+        assert!(decoded("/files/%ff%fe").is_none());
+    }
+
+    #[test]
+    fn url_authority() {
+        let authority = |data: &str| Url::new(data).authority();
+
+        assert_eq!(
+            authority("http://example.com:8080/index.html?a=1").unwrap(),
+            "example.com:8080"
+        );
+        assert_eq!(authority("HTTPS://user:pass@[::1]").unwrap(), "[::1]");
+        assert!(authority("/index.html").is_none());
+        assert!(authority("ftp://example.com/").is_none());
+
+        let url = Url::new("http://example.com:8080/files/a%20b.txt?a=1");
+        assert_eq!(url.resource(), "/files/a%20b.txt");
+        assert_eq!(url.decoded_resource().unwrap(), "/files/a b.txt");
+        assert_eq!(url.query(), "a=1");
+
+        let url = Url::new("http://example.com?a=1");
+        assert_eq!(url.resource(), "/");
+        assert_eq!(url.query(), "a=1");
+    }
+
+    #[test]
+    fn url_percent_decode() {
+        assert_eq!(percent_decode("a%20b%2Fc").unwrap(), "a b/c");
+        assert_eq!(percent_decode("%c3%a9t%C3%A9").unwrap(), "été");
+        assert_eq!(percent_decode("plain").unwrap(), "plain");
+        assert_eq!(percent_decode("").unwrap(), "");
+
+        for invalid in ["%", "a%2", "%zz", "%%41", "%ff%fe", "%C3"] {
+            assert!(percent_decode(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn url_percent_encode() {
+        assert_eq!(percent_encode("a b/c?d#e%"), "a%20b%2Fc%3Fd%23e%25");
+        assert_eq!(percent_encode("Az09-._~"), "Az09-._~");
+        assert_eq!(percent_encode("été 😀"), "%C3%A9t%C3%A9%20%F0%9F%98%80");
+
+        for data in ["été 😀", "100% sure", "a+b=c&d"] {
+            assert_eq!(percent_decode(&percent_encode(data)).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn url_query_params() {
+        let url = Url::new("/search?a=1&b=2");
+        assert_eq!(url.resource(), "/search");
+        assert_eq!(url.query_params(), params(&[("a", "1"), ("b", "2")]));
+        assert_eq!(url.query_param("b").unwrap(), "2");
+        assert!(url.query_param("c").is_none());
+    }
+
+    #[test]
+    fn url_query_params_percent_encoded() {
+        let url = Url::new("/search?q=hello%20world&name%3D=a+b&bad=%zz%");
+        assert_eq!(
+            url.query_params(),
+            params(&[("q", "hello world"), ("name=", "a b"), ("bad", "%zz%")])
+        );
+    }
+
+    #[test]
+    fn url_query_params_empty_values() {
+        let url = Url::new("/search?x&y=&&x=1");
+        assert_eq!(
+            url.query_params(),
+            params(&[("x", ""), ("y", ""), ("x", "1")])
+        );
+        assert_eq!(url.query_param("x").unwrap(), "");
+
+        assert!(Url::new("/search").query_params().is_empty());
+        assert!(Url::new("/search?").query_params().is_empty());
+    }
 }
@@ -0,0 +1,246 @@
+use std::io::{self, Read, Write};
+
+use base64::prelude::*;
+use sha1::{Digest, Sha1};
+
+use crate::{
+    common::{ErrorCode, ResponseCode, SuccessCode},
+    request::{HttpRequest, HttpRequestMethod},
+    response::{HttpResponse, HttpResponseBuilder},
+};
+
+// Appended to the key of the client, RFC 6455 section 1.3
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const WEBSOCKET_VERSION: &str = "13";
+// Larger frames close the connection, the whole payload is kept in memory
+const MAX_FRAME_SIZE: u64 = 16 * 1024 * 1024; // 16 MB
+
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+fn has_token(header: Option<&String>, token: &str) -> bool {
+    header.is_some_and(|header| {
+        header
+            .split(',')
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    })
+}
+
+pub fn is_upgrade_request(request: &HttpRequest) -> bool {
+    request.get_method() == HttpRequestMethod::GET
+        && has_token(request.content().get_header("upgrade"), "websocket")
+        && has_token(request.content().get_header("connection"), "upgrade")
+}
+
+pub fn accept_key(key: &str) -> String {
+    let digest = Sha1::digest(format!("{}{}", key, WEBSOCKET_GUID));
+    BASE64_STANDARD.encode(digest)
+}
+
+// `101 Switching Protocols`, or 400 when the key or the version of the client aren't valid
+pub fn handshake_response(request: &HttpRequest) -> HttpResponse {
+    let version = request.get_version();
+    let key = request
+        .content()
+        .get_header("sec-websocket-key")
+        .filter(|key| {
+            BASE64_STANDARD
+                .decode(key.trim())
+                .is_ok_and(|key| key.len() == 16)
+        });
+    let version_supported = request
+        .content()
+        .get_header("sec-websocket-version")
+        .is_some_and(|websocket_version| websocket_version.trim() == WEBSOCKET_VERSION);
+
+    match key {
+        Some(key) if version_supported => HttpResponseBuilder::new(
+            ResponseCode::Success(SuccessCode::SwitchingProtocols),
+            &version,
+            None,
+        )
+        .header("upgrade", "websocket")
+        .header("connection", "Upgrade")
+        .header("sec-websocket-accept", accept_key(key.trim()))
+        .build(),
+        _ => HttpResponseBuilder::new(ResponseCode::Error(ErrorCode::BadRequest), &version, None)
+            .header("sec-websocket-version", WEBSOCKET_VERSION)
+            .build(),
+    }
+}
+
+struct Frame {
+    // FIN bit and opcode
+    header: u8,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    fn opcode(&self) -> u8 {
+        self.header & 0x0F
+    }
+}
+
+// `None` once the client closed the connection between frames
+fn read_frame(stream: &mut impl Read) -> io::Result<Option<Frame>> {
+    let mut header = [0u8; 2];
+    match stream.read(&mut header[..1])? {
+        0 => return Ok(None),
+        _ => stream.read_exact(&mut header[1..])?,
+    }
+
+    let payload_len = match header[1] & 0x7F {
+        126 => {
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len)?;
+            u64::from(u16::from_be_bytes(len))
+        }
+        127 => {
+            let mut len = [0u8; 8];
+            stream.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => u64::from(len),
+    };
+    if payload_len > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "WebSocket frame is too large",
+        ));
+    }
+
+    // Frames from clients are always masked
+    if header[1] & 0x80 == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Unmasked WebSocket frame",
+        ));
+    }
+    let mut mask = [0u8; 4];
+    stream.read_exact(&mut mask)?;
+
+    let mut payload = vec![0u8; payload_len as usize];
+    stream.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+
+    Ok(Some(Frame {
+        header: header[0],
+        payload,
+    }))
+}
+
+fn write_frame(stream: &mut impl Write, header: u8, payload: &[u8]) -> io::Result<()> {
+    let mut frame = vec![header];
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)?;
+    stream.flush()
+}
+
+// Sends every data frame back as is, until the client closes the connection
+pub fn echo_frames(stream: &mut (impl Read + Write)) -> io::Result<()> {
+    while let Some(frame) = read_frame(stream)? {
+        match frame.opcode() {
+            OPCODE_CLOSE => return write_frame(stream, 0x80 | OPCODE_CLOSE, &frame.payload),
+            OPCODE_PING => write_frame(stream, 0x80 | OPCODE_PONG, &frame.payload)?,
+            OPCODE_PONG => {}
+            _ => write_frame(stream, frame.header, &frame.payload)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    // Reads from the client frames, writes to a separate buffer
+    struct TestConnection {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for TestConnection {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for TestConnection {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn client_frame(header: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut frame = vec![header, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4]),
+        );
+        frame
+    }
+
+    #[test]
+    fn websocket_accept_key() {
+        // Example from RFC 6455
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn websocket_echo_frames() {
+        let mut input = client_frame(0x81, b"Hello");
+        input.extend(client_frame(0x89, b"ping"));
+        input.extend(client_frame(0x88, &[0x03, 0xe8]));
+        input.extend(client_frame(0x81, b"after close"));
+        let mut connection = TestConnection {
+            input: Cursor::new(input),
+            output: Vec::new(),
+        };
+
+        echo_frames(&mut connection).unwrap();
+
+        let mut expected = vec![0x81, 5];
+        expected.extend_from_slice(b"Hello");
+        expected.extend_from_slice(&[0x8A, 4]);
+        expected.extend_from_slice(b"ping");
+        expected.extend_from_slice(&[0x88, 2, 0x03, 0xe8]);
+        assert_eq!(connection.output, expected);
+    }
+
+    #[test]
+    fn websocket_unmasked_frame() {
+        let mut connection = TestConnection {
+            input: Cursor::new(vec![0x81, 0x02, b'h', b'i']),
+            output: Vec::new(),
+        };
+        assert!(echo_frames(&mut connection).is_err());
+        assert!(connection.output.is_empty());
+    }
+}
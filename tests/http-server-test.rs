@@ -0,0 +1,91 @@
+use std::net::SocketAddr;
+
+use http_server::{
+    common::ErrorCode, config::ServerConfig, handel_connection, handel_connection_with_responses,
+    request::parse_http_request, testing::MockTcpStream, ContentEncoding, HttpRequestMethod,
+    HttpResponseBuilder, ResponseCode,
+};
+
+fn client_address() -> SocketAddr {
+    SocketAddr::from(([127, 0, 0, 1], 51234))
+}
+
+#[test]
+fn mock_stream_echo() {
+    let mut stream = MockTcpStream::new(b"GET /echo/hello HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    handel_connection(&mut stream, client_address(), &ServerConfig::default()).unwrap();
+
+    let response = stream.written_string();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("content-length: 5\r\n"));
+    assert!(response.ends_with("\r\n\r\nhello"));
+}
+
+#[test]
+fn mock_stream_pipelined_requests() {
+    let mut stream = MockTcpStream::new(
+        b"GET /echo/first HTTP/1.1\r\nHost: localhost\r\n\r\n\
+          GET /missing.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    handel_connection(&mut stream, client_address(), &ServerConfig::default()).unwrap();
+
+    let response = stream.written_string();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("first"));
+    assert!(response.contains("HTTP/1.1 404 Not Found\r\n"));
+}
+
+#[test]
+fn mock_stream_returned_responses() {
+    let mut stream = MockTcpStream::new(
+        b"GET /echo/first HTTP/1.1\r\nHost: localhost\r\n\r\n\
+          GET /missing.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+    );
+    let responses =
+        handel_connection_with_responses(&mut stream, client_address(), &ServerConfig::default())
+            .unwrap();
+
+    assert_eq!(responses.len(), 2);
+    assert_eq!(responses[0].status_code, 200);
+    assert_eq!(responses[0].body_size, 5);
+    assert_eq!(responses[1].status_code, 404);
+    assert_eq!(responses[1].headers.get("connection").unwrap(), "close");
+}
+
+#[test]
+fn response_builder_from_outside() {
+    let response = HttpResponseBuilder::new(
+        ResponseCode::Error(ErrorCode::NotFound),
+        "1.1",
+        Some(ContentEncoding::Gzip),
+    )
+    .header("content-type", "text/plain")
+    .body(b"gone")
+    .build();
+
+    assert_eq!(
+        response.status_code(),
+        ResponseCode::Error(ErrorCode::NotFound)
+    );
+    // Too small to be compressed
+    assert!(response.content().get_header("content-encoding").is_none());
+    let raw_response = String::from_utf8(response.as_bytes()).unwrap();
+    assert!(raw_response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    assert!(raw_response.ends_with("\r\n\r\ngone"));
+}
+
+#[test]
+fn mock_stream_parse_request() {
+    let mut stream = MockTcpStream::new(
+        b"POST /upload.txt HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello",
+    );
+    let request = parse_http_request(&mut stream).unwrap();
+
+    assert_eq!(request.get_method(), HttpRequestMethod::POST);
+    assert_eq!(request.get_url().resource(), "/upload.txt");
+    assert_eq!(request.content().get_header("host").unwrap(), "localhost");
+    assert_eq!(request.content().get_body(), b"hello");
+
+    // Nothing is left for another request
+    assert!(parse_http_request(&mut stream).is_err());
+}